@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::io;
+use serde::{Deserialize, Serialize};
+use context::edge_of;
+use nodes::NodeKey;
+use packets::invalid_data;
+use partition::{self, PartitionPlan};
+
+/// Bumped whenever the layout of `AssignmentChunk` changes.
+pub const ASSIGNMENT_FORMAT_VERSION: u32 = 1;
+
+// bincode size of everything in a chunk but its entries
+const CHUNK_HEADER_LEN: usize = 4 + 8 + 4 + 4 + 8;
+// bincode size of one `(NodeKey, u32)` entry
+const CHUNK_ENTRY_LEN: usize = 4 + 1 + 4;
+
+/// Which rank owns each node, as decided by the coordinator in `epoch`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PartitionAssignment {
+    pub epoch: u64,
+    // sorted by key, so equal assignments encode identically
+    pub owners: Vec<(NodeKey, u32)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct AssignmentChunk {
+    version: u32,
+    epoch: u64,
+    index: u32,
+    count: u32,
+    owners: Vec<(NodeKey, u32)>,
+}
+
+impl PartitionAssignment {
+    /// Plan `i` is owned by `ranks[i]`. A node listed in several plans goes
+    /// to the first of them.
+    pub fn from_plans(epoch: u64, plans: &[PartitionPlan], ranks: &[u32]) -> Self {
+        assert_eq!(plans.len(), ranks.len(), "every plan needs a rank");
+        let owners: BTreeMap<NodeKey, u32> = partition::owners(plans).into_iter()
+            .map(|(key, plan)| (key, ranks[plan]))
+            .collect();
+        PartitionAssignment {
+            epoch,
+            owners: owners.into_iter().collect(),
+        }
+    }
+
+    /// Whether both assign every node to the same rank, regardless of epoch.
+    pub fn same_owners(&self, other: &PartitionAssignment) -> bool {
+        self.owners == other.owners
+    }
+
+    /// Owner rank per edge index, `0` where no node of that index is
+    /// assigned. With several `nth` variants the lowest one decides, and for
+    /// context-sensitive keys (see `context`) the lowest context.
+    pub fn owner_by_index(&self, len: usize) -> Vec<u32> {
+        let mut result = vec![0; len];
+        let mut seen = vec![false; len];
+        for ((idx, _), rank) in &self.owners {
+            let idx = edge_of(*idx) as usize;
+            if idx < len && !seen[idx] {
+                result[idx] = *rank;
+                seen[idx] = true;
+            }
+        }
+        result
+    }
+
+    /// Owner rank of the packed index `idx` (see `context`), so callers of
+    /// an edge keep their own owners; with several `nth` variants the lowest
+    /// one decides. `None` where no node of `idx` is assigned.
+    pub fn owner_of(&self, idx: u32) -> Option<u32> {
+        let first = self.owners.partition_point(|((i, _), _)| *i < idx);
+        self.owners.get(first).filter(|((i, _), _)| *i == idx).map(|(_, rank)| *rank)
+    }
+
+    /// Encodes the assignment as packets of at most `max_len` bytes each.
+    pub fn to_packets(&self, max_len: usize) -> Vec<Vec<u8>> {
+        assert!(max_len > CHUNK_HEADER_LEN + CHUNK_ENTRY_LEN, "packets too small for an entry");
+        let per_chunk = (max_len - CHUNK_HEADER_LEN) / CHUNK_ENTRY_LEN;
+        let chunks: Vec<&[(NodeKey, u32)]> = if self.owners.is_empty() {
+            vec![&[]]
+        } else {
+            self.owners.chunks(per_chunk).collect()
+        };
+        let count = chunks.len() as u32;
+        chunks.into_iter().enumerate().map(|(index, owners)| {
+            bincode::serialize(&AssignmentChunk {
+                version: ASSIGNMENT_FORMAT_VERSION,
+                epoch: self.epoch,
+                index: index as u32,
+                count,
+                owners: owners.to_vec(),
+            }).unwrap()
+        }).collect()
+    }
+}
+
+/// Collects the packets of an assignment and hands it out only once all of
+/// them arrived. Packets of epochs older than the newest one seen are
+/// dropped, so a worker never applies a stale or partial assignment. A
+/// packet that does not decode or does not fit its epoch is an error and
+/// changes nothing.
+#[derive(Default)]
+pub struct AssignmentAssembler {
+    epoch: Option<u64>,
+    chunks: Vec<Option<Vec<(NodeKey, u32)>>>,
+    applied: Option<u64>,
+}
+
+impl AssignmentAssembler {
+    pub fn new() -> Self {
+        AssignmentAssembler::default()
+    }
+
+    /// Epoch of the last complete assignment returned by `push`.
+    pub fn applied_epoch(&self) -> Option<u64> {
+        self.applied
+    }
+
+    pub fn push(&mut self, packet: &[u8]) -> io::Result<Option<PartitionAssignment>> {
+        let version: u32 = bincode::deserialize(packet).map_err(invalid_data)?;
+        if version != ASSIGNMENT_FORMAT_VERSION {
+            return Err(invalid_data(format!("unsupported assignment format version {}", version)));
+        }
+        let chunk: AssignmentChunk = bincode::deserialize(packet).map_err(invalid_data)?;
+
+        if self.applied.is_some_and(|applied| chunk.epoch <= applied)
+            || self.epoch.is_some_and(|epoch| chunk.epoch < epoch) {
+            return Ok(None);
+        }
+        let count = if self.epoch == Some(chunk.epoch) { self.chunks.len() } else { chunk.count as usize };
+        if chunk.count as usize != count || chunk.index >= chunk.count {
+            return Err(invalid_data(format!("assignment chunk {} of {} does not fit epoch {}",
+                                            chunk.index, chunk.count, chunk.epoch)));
+        }
+        if self.epoch != Some(chunk.epoch) {
+            self.epoch = Some(chunk.epoch);
+            self.chunks = vec![None; count];
+        }
+        self.chunks[chunk.index as usize] = Some(chunk.owners);
+        if self.chunks.iter().any(|c| c.is_none()) {
+            return Ok(None);
+        }
+
+        let owners = self.chunks.drain(..).flatten().flatten().collect();
+        self.applied = self.epoch.take();
+        Ok(Some(PartitionAssignment {
+            epoch: chunk.epoch,
+            owners,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use context::context_idx;
+
+    fn plan(keys: Vec<NodeKey>) -> PartitionPlan {
+        PartitionPlan {
+            plan: keys,
+            weight: 0,
+            dependencies: HashMap::new(),
+        }
+    }
+
+    fn sample(epoch: u64) -> PartitionAssignment {
+        let plans = vec![
+            plan((0..100).map(|i| (i, 0)).collect()),
+            plan((100..150).map(|i| (i, 1)).collect()),
+        ];
+        PartitionAssignment::from_plans(epoch, &plans, &[1, 2])
+    }
+
+    #[test]
+    fn test_packets_round_trip() {
+        let assignment = sample(3);
+        let packets = assignment.to_packets(128);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= 128));
+
+        let mut assembler = AssignmentAssembler::new();
+        let mut result = None;
+        // arrival order does not matter
+        for packet in packets.iter().rev() {
+            assert!(result.is_none());
+            result = assembler.push(packet).unwrap();
+        }
+        assert_eq!(result.unwrap(), assignment);
+        assert_eq!(assembler.applied_epoch(), Some(3));
+    }
+
+    #[test]
+    fn test_stale_epoch_dropped() {
+        let mut assembler = AssignmentAssembler::new();
+        let new = sample(5).to_packets(128);
+        let old = sample(4).to_packets(128);
+
+        assert!(assembler.push(&new[0]).unwrap().is_none());
+        // an older epoch arriving mid-way neither completes nor resets
+        for packet in &old {
+            assert!(assembler.push(packet).unwrap().is_none());
+        }
+        let mut result = None;
+        for packet in &new[1..] {
+            result = assembler.push(packet).unwrap();
+        }
+        assert_eq!(result.unwrap().epoch, 5);
+        // nor is anything accepted after a newer epoch was applied
+        assert!(assembler.push(&old[0]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_empty_assignment() {
+        let assignment = PartitionAssignment::from_plans(1, &[], &[]);
+        let packets = assignment.to_packets(64);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(AssignmentAssembler::new().push(&packets[0]).unwrap().unwrap(), assignment);
+    }
+
+    #[test]
+    fn test_bad_packets_rejected() {
+        let mut assembler = AssignmentAssembler::new();
+        let packets = sample(2).to_packets(128);
+        assert!(assembler.push(&packets[0]).unwrap().is_none());
+
+        assert!(assembler.push(&[1]).is_err());
+        for (index, count) in [(1, 1), (0, 0), (packets.len() as u32, packets.len() as u32)] {
+            let chunk = AssignmentChunk { version: ASSIGNMENT_FORMAT_VERSION, epoch: 2, index, count, owners: vec![] };
+            assert!(assembler.push(&bincode::serialize(&chunk).unwrap()).is_err());
+        }
+
+        let mut result = None;
+        for packet in &packets[1..] {
+            result = assembler.push(packet).unwrap();
+        }
+        assert_eq!(result.unwrap(), sample(2));
+    }
+
+    #[test]
+    fn test_owner_by_index() {
+        let plans = vec![plan(vec![(1, 1), (2, 0)]), plan(vec![(1, 0)])];
+        let assignment = PartitionAssignment::from_plans(0, &plans, &[1, 2]);
+        assert_eq!(assignment.owner_by_index(4), vec![0, 2, 1, 0]);
+
+        assert_eq!(assignment.owner_of(1), Some(2));
+        assert_eq!(assignment.owner_of(3), None);
+
+        let (a, b) = (context_idx(3, 5).unwrap(), context_idx(3, 9).unwrap());
+        let contextual = vec![plan(vec![(a, 0)]), plan(vec![(b, 0)])];
+        let assignment = PartitionAssignment::from_plans(0, &contextual, &[1, 2]);
+        assert_eq!(assignment.owner_by_index(4)[3], if a < b { 1 } else { 2 });
+        // both callers keep their own owner
+        assert_eq!(assignment.owner_of(a), Some(1));
+        assert_eq!(assignment.owner_of(b), Some(2));
+    }
+}
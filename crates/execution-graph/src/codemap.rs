@@ -0,0 +1,130 @@
+use std::collections::{BTreeSet, HashMap};
+use context::edge_of;
+use nodes::NodeKey;
+
+/// Flag of a `PcTableEntry` that starts a function.
+pub const PC_FLAG_FUNC_ENTRY: usize = 1;
+
+/// One entry of the `-fsanitize-coverage=pc-table` table. Entry `i`
+/// describes edge index `i`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[repr(C)]
+pub struct PcTableEntry {
+    pub pc: usize,
+    pub flags: usize,
+}
+
+/// Where a function entry PC symbolizes to.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Symbol {
+    pub function: String,
+    pub file: String,
+}
+
+/// Whether code is split into whole functions or whole source files.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Granularity {
+    Function,
+    File,
+}
+
+/// Maps edge indices to the function and source file they are in. The
+/// compiler emits the PC table function by function with the first edge of
+/// each flagged as an entry, so every edge belongs to the last entry at or
+/// before it.
+#[derive(Clone, Debug, Default)]
+pub struct CodeMap {
+    // function of every edge index, `None` before the first entry
+    function_of: Vec<Option<usize>>,
+    functions: Vec<Symbol>,
+    // file of every function
+    file_of: Vec<usize>,
+    files: Vec<String>,
+}
+
+impl CodeMap {
+    /// Builds the map from a PC table; `symbolize` is asked once per
+    /// function, with the PC of its entry.
+    pub fn from_pc_table<F: FnMut(usize) -> Symbol>(table: &[PcTableEntry], mut symbolize: F) -> Self {
+        let mut map = CodeMap::default();
+        let mut file_index: HashMap<String, usize> = HashMap::new();
+        let mut current = None;
+        for entry in table {
+            if entry.flags & PC_FLAG_FUNC_ENTRY != 0 {
+                let symbol = symbolize(entry.pc);
+                let next_file = map.files.len();
+                let file = *file_index.entry(symbol.file.clone()).or_insert(next_file);
+                if file == next_file {
+                    map.files.push(symbol.file.clone());
+                }
+                map.file_of.push(file);
+                map.functions.push(symbol);
+                current = Some(map.functions.len() - 1);
+            }
+            map.function_of.push(current);
+        }
+        map
+    }
+
+    pub fn function(&self, edge: u32) -> Option<&Symbol> {
+        self.function_of.get(edge as usize).copied().flatten().map(|f| &self.functions[f])
+    }
+
+    /// Function or file index of `edge`.
+    pub fn unit(&self, edge: u32, granularity: Granularity) -> Option<usize> {
+        let function = self.function_of.get(edge as usize).copied().flatten()?;
+        Some(match granularity {
+            Granularity::Function => function,
+            Granularity::File => self.file_of[function],
+        })
+    }
+
+    pub fn unit_name(&self, unit: usize, granularity: Granularity) -> &str {
+        match granularity {
+            Granularity::Function => &self.functions[unit].function,
+            Granularity::File => &self.files[unit],
+        }
+    }
+
+    /// Names of the functions or files the given nodes are in, sorted and
+    /// without duplicates. Context-sensitive keys count for their edge.
+    pub fn unit_names(&self, keys: &[NodeKey], granularity: Granularity) -> Vec<&str> {
+        let names: BTreeSet<&str> = keys.iter()
+            .filter_map(|(idx, _)| self.unit(edge_of(*idx), granularity))
+            .map(|unit| self.unit_name(unit, granularity))
+            .collect();
+        names.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> CodeMap {
+        // edges 0-2 in main, 3-4 in parse, 5 in helper; parse and helper share a file
+        let flags = [1, 0, 0, 1, 0, 1];
+        let table: Vec<PcTableEntry> = flags.iter().enumerate()
+            .map(|(i, flags)| PcTableEntry { pc: 0x1000 + i * 0x10, flags: *flags })
+            .collect();
+        CodeMap::from_pc_table(&table, |pc| match pc {
+            0x1000 => Symbol { function: "main".to_string(), file: "main.c".to_string() },
+            0x1030 => Symbol { function: "parse".to_string(), file: "parse.c".to_string() },
+            _ => Symbol { function: "helper".to_string(), file: "parse.c".to_string() },
+        })
+    }
+
+    #[test]
+    fn test_units() {
+        let map = sample();
+        assert_eq!(map.function(2).unwrap().function, "main");
+        assert_eq!(map.function(4).unwrap().function, "parse");
+        assert_eq!(map.unit(3, Granularity::Function), Some(1));
+        assert_eq!(map.unit(5, Granularity::Function), Some(2));
+        assert_eq!(map.unit(5, Granularity::File), map.unit(3, Granularity::File));
+        assert_eq!(map.unit(6, Granularity::File), None);
+        assert_eq!(map.unit_names(&[(4, 0), (5, 2), (0, 0)], Granularity::Function),
+                   vec!["helper", "main", "parse"]);
+        assert_eq!(map.unit_names(&[(4, 0), (5, 2)], Granularity::File), vec!["parse.c"]);
+    }
+}
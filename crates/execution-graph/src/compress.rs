@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use context::EDGE_BITS;
+use nodes::NodeKey;
+
+/// Longest loop body `TraceCompressor::default` de-duplicates.
+pub const DEFAULT_MAX_CYCLE_LEN: usize = 16;
+
+/// AFL-style bucket of the `hits`-th hit of an edge: 1, 2, 3, 4-7, 8-15,
+/// 16-31, 32-127 and 128+ hits map to buckets 0 to 7.
+pub fn hit_bucket(hits: u32) -> u8 {
+    match hits {
+        0 | 1 => 0,
+        2 => 1,
+        3 => 2,
+        4..=7 => 3,
+        8..=15 => 4,
+        16..=31 => 5,
+        32..=127 => 6,
+        _ => 7,
+    }
+}
+
+/// Turns a raw edge trace into the keys handed to `DGraph::add_trace`, so
+/// that the graph does not grow with loop trip counts. The `nth` of a key is
+/// the hit-count bucket of that edge so far rather than the exact count, and
+/// a cycle of at most `max_cycle_len` keys repeated back to back is kept
+/// once. A loop therefore yields the same keys whether it runs 40 or 100
+/// times.
+pub struct TraceCompressor {
+    max_cycle_len: usize,
+    // per plain edge index during `compress`: its hits, and one past the
+    // position of its last key; grown as needed and all zero in between
+    seen: Vec<(u32, usize)>,
+    // plain edge indices seen in the current `compress`, to reset them
+    touched: Vec<u32>,
+    // the same for context-sensitive indices (see `context`), which are
+    // too sparse for a table
+    sparse_seen: HashMap<u32, (u32, usize)>,
+}
+
+impl Default for TraceCompressor {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CYCLE_LEN)
+    }
+}
+
+impl TraceCompressor {
+    pub fn new(max_cycle_len: usize) -> Self {
+        TraceCompressor {
+            max_cycle_len,
+            seen: vec![],
+            touched: vec![],
+            sparse_seen: HashMap::new(),
+        }
+    }
+
+    fn seen(&mut self, idx: u32) -> &mut (u32, usize) {
+        if idx >= 1 << EDGE_BITS {
+            return self.sparse_seen.entry(idx).or_insert((0, 0));
+        }
+        let i = idx as usize;
+        if i >= self.seen.len() {
+            self.seen.resize(i + 1, (0, 0));
+        }
+        if self.seen[i] == (0, 0) {
+            self.touched.push(idx);
+        }
+        &mut self.seen[i]
+    }
+
+    pub fn compress(&mut self, trace: &[u32]) -> Vec<NodeKey> {
+        let mut keys: Vec<NodeKey> = Vec::with_capacity(trace.len());
+        // one past the position of the previous key of the same index
+        let mut previous: Vec<usize> = Vec::with_capacity(trace.len());
+        for idx in trace {
+            let (hits, last) = self.seen(*idx);
+            *hits = hits.saturating_add(1);
+            let key = (*idx, hit_bucket(*hits));
+            previous.push(std::mem::replace(last, keys.len() + 1));
+            keys.push(key);
+
+            // drop the cycle just completed if it repeats the one before it;
+            // a period can only match where the index was seen before, so
+            // this follows those positions rather than trying every period
+            let len = keys.len();
+            let mut at = previous[len - 1];
+            while at != 0 {
+                let period = len - at;
+                if period > self.max_cycle_len || 2 * period > len {
+                    break;
+                }
+                if keys[at - 1] == key && keys[len - period..] == keys[len - 2 * period..len - period] {
+                    // the dropped keys were last seen where they repeat
+                    for dropped in (len - period..len).rev() {
+                        self.seen(keys[dropped].0).1 = previous[dropped];
+                    }
+                    keys.truncate(len - period);
+                    previous.truncate(len - period);
+                    break;
+                }
+                at = previous[at - 1];
+            }
+        }
+        for idx in self.touched.drain(..) {
+            self.seen[idx as usize] = (0, 0);
+        }
+        self.sparse_seen.clear();
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn looped(body: &[u32], iterations: usize) -> Vec<u32> {
+        let mut trace = vec![1];
+        for _ in 0..iterations {
+            trace.extend_from_slice(body);
+        }
+        trace.push(9);
+        trace
+    }
+
+    // every period tried after every key, the way the compressor behaves
+    fn reference(trace: &[u32], max_cycle_len: usize) -> Vec<NodeKey> {
+        let mut hits: HashMap<u32, u32> = HashMap::new();
+        let mut keys: Vec<NodeKey> = vec![];
+        for idx in trace {
+            let hits = hits.entry(*idx).or_insert(0);
+            *hits += 1;
+            keys.push((*idx, hit_bucket(*hits)));
+            for period in 1..=max_cycle_len {
+                let len = keys.len();
+                if len < 2 * period {
+                    break;
+                }
+                if keys[len - period..] == keys[len - 2 * period..len - period] {
+                    keys.truncate(len - period);
+                    break;
+                }
+            }
+        }
+        keys
+    }
+
+    proptest! {
+        #[test]
+        fn prop_matches_reference(trace in proptest::collection::vec(0u32..6, 0..200)) {
+            let mut compressor = TraceCompressor::new(4);
+            prop_assert_eq!(compressor.compress(&trace), reference(&trace, 4));
+            prop_assert_eq!(compressor.compress(&trace), reference(&trace, 4));
+        }
+    }
+
+    #[test]
+    fn test_hit_bucket() {
+        let buckets: Vec<u8> = vec![1, 2, 3, 4, 7, 8, 15, 16, 31, 32, 127, 128, 5000]
+            .into_iter().map(hit_bucket).collect();
+        assert_eq!(buckets, vec![0, 1, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7]);
+    }
+
+    #[test]
+    fn test_straight_line_unchanged() {
+        let keys = TraceCompressor::default().compress(&[1, 2, 3, 2]);
+        assert_eq!(keys, vec![(1, 0), (2, 0), (3, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_loop_collapses() {
+        let mut compressor = TraceCompressor::default();
+        let keys = compressor.compress(&looped(&[2, 3, 4], 1000));
+        // one iteration per bucket, plus the entry and exit
+        assert_eq!(keys.len(), 8 * 3 + 2);
+        assert_eq!(keys.last(), Some(&(9, 0)));
+    }
+
+    #[test]
+    fn test_trip_counts_in_a_bucket_agree() {
+        let mut compressor = TraceCompressor::default();
+        let forty = compressor.compress(&looped(&[2, 3], 40));
+        let hundred = compressor.compress(&looped(&[2, 3], 100));
+        let few = compressor.compress(&looped(&[2, 3], 5));
+        assert_eq!(forty, hundred);
+        assert_ne!(forty, few);
+    }
+
+    #[test]
+    fn test_reused_between_traces() {
+        let mut compressor = TraceCompressor::default();
+        let sparse = 5 << EDGE_BITS | 2;
+        let first = compressor.compress(&[1, 1, sparse, sparse]);
+        assert_eq!(first, vec![(1, 0), (1, 1), (sparse, 0), (sparse, 1)]);
+        // hit counts start over with every trace
+        assert_eq!(compressor.compress(&[1, 1, sparse, sparse]), first);
+    }
+
+    #[test]
+    fn test_long_cycles_kept() {
+        let body: Vec<u32> = (10..20).collect();
+        let keys = TraceCompressor::new(4).compress(&looped(&body, 3));
+        assert_eq!(keys.len(), 3 * 10 + 2);
+    }
+}
@@ -0,0 +1,102 @@
+//! Context-sensitive node keys. The calling context of an edge is packed
+//! into the high bits of `NodeKey::0`, so the same edge reached from
+//! different callers becomes distinct nodes while graphs, deltas, partitions
+//! and assignments keep working on plain `(idx, nth)` keys. Without a
+//! context, or with context `0`, the packed index is just the edge index.
+
+use std::fmt;
+
+/// Low bits of a packed index that hold the edge index.
+pub const EDGE_BITS: u32 = 20;
+/// High bits of a packed index that hold the folded calling-context hash.
+pub const CONTEXT_BITS: u32 = 32 - EDGE_BITS;
+
+const EDGE_MASK: u32 = (1 << EDGE_BITS) - 1;
+
+// 2^64 divided by the golden ratio, spreads any input over the high bits
+const FIBONACCI: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// An edge index too wide to be packed with a context.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EdgeTooWide(pub u32);
+
+impl fmt::Display for EdgeTooWide {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "edge index {} does not fit in {} bits", self.0, EDGE_BITS)
+    }
+}
+
+/// Calling context inside a call made from `call_site` in `context`. The
+/// order of calls matters, so `a` calling `b` differs from `b` calling `a`.
+pub fn enter_call(context: u64, call_site: u64) -> u64 {
+    (context.rotate_left(7) ^ call_site).wrapping_mul(FIBONACCI)
+}
+
+/// Folds a call-stack hash of any width down to `CONTEXT_BITS` bits, by
+/// multiplicative hashing so every input bit reaches the result. Context
+/// `0` folds to `0`.
+pub fn fold_context(hash: u64) -> u32 {
+    (hash.wrapping_mul(FIBONACCI) >> (64 - CONTEXT_BITS)) as u32
+}
+
+/// Packed index of `edge` reached in calling context `context`, which is
+/// folded with `fold_context`.
+pub fn context_idx(edge: u32, context: u64) -> Result<u32, EdgeTooWide> {
+    if edge > EDGE_MASK {
+        return Err(EdgeTooWide(edge));
+    }
+    Ok(fold_context(context) << EDGE_BITS | edge)
+}
+
+/// Edge index of a packed index.
+pub fn edge_of(idx: u32) -> u32 {
+    idx & EDGE_MASK
+}
+
+/// Folded calling context of a packed index.
+pub fn context_of(idx: u32) -> u32 {
+    idx >> EDGE_BITS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dgraph::DGraph;
+
+    #[test]
+    fn test_pack() {
+        let idx = context_idx(1234, 0xdead_beef_cafe).unwrap();
+        assert_eq!(edge_of(idx), 1234);
+        assert_eq!(context_of(idx), fold_context(0xdead_beef_cafe));
+        assert!(context_of(idx) < 1 << CONTEXT_BITS);
+        // no context is the plain edge index
+        assert_eq!(context_idx(1234, 0), Ok(1234));
+        assert_eq!(context_idx(1 << EDGE_BITS, 1), Err(EdgeTooWide(1 << EDGE_BITS)));
+    }
+
+    #[test]
+    fn test_contexts_spread() {
+        // call chains through the same few call sites, in different orders
+        let sites = [0x401000, 0x401040, 0x402200, 0x40a010];
+        let mut contexts = std::collections::HashSet::new();
+        for chain in 0..256u64 {
+            let context = (0..4).fold(0, |context, depth| enter_call(context, sites[(chain >> (2 * depth)) as usize & 3]));
+            contexts.insert(fold_context(context));
+        }
+        assert!(contexts.len() > 240, "{} distinct contexts", contexts.len());
+        assert_ne!(enter_call(enter_call(0, sites[0]), sites[1]), enter_call(enter_call(0, sites[1]), sites[0]));
+    }
+
+    #[test]
+    fn test_callers_split_nodes() {
+        let (a, b) = (context_idx(7, 0x11).unwrap(), context_idx(7, 0x22).unwrap());
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (a, 0)]);
+        graph.add_trace(vec![(2, 0), (b, 0)]);
+        assert_eq!(graph.len(), 5);
+
+        let plans = graph.partition(2);
+        let owner = |key| plans.iter().position(|p| p.plan.contains(&key)).unwrap();
+        assert_ne!(owner((a, 0)), owner((b, 0)));
+    }
+}
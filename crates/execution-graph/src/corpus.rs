@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+use nodes::NodeKey;
+
+/// Identifies an input of the fuzzer's corpus.
+pub type CorpusId = usize;
+
+/// Which corpus inputs reach each node, fed with the trace of every input
+/// added to the corpus.
+#[derive(Clone, Debug, Default)]
+pub struct CorpusIndex {
+    // sorted and without duplicates
+    inputs: HashMap<NodeKey, Vec<CorpusId>>,
+}
+
+impl CorpusIndex {
+    pub fn new() -> Self {
+        CorpusIndex::default()
+    }
+
+    /// Records that `input` reaches every node of `trace`, i.e. the keys it
+    /// was handed to `add_trace` with.
+    pub fn record(&mut self, input: CorpusId, trace: &[NodeKey]) {
+        for key in trace {
+            let inputs = self.inputs.entry(*key).or_default();
+            if let Err(i) = inputs.binary_search(&input) {
+                inputs.insert(i, input);
+            }
+        }
+    }
+
+    /// Inputs reaching `key`, in ascending order.
+    pub fn inputs(&self, key: &NodeKey) -> &[CorpusId] {
+        self.inputs.get(key).map_or(&[], |inputs| inputs.as_slice())
+    }
+
+    /// Inputs reaching any of `keys`, in ascending order and without
+    /// duplicates; e.g. the seeds of a newly owned `PartitionPlan`.
+    pub fn inputs_reaching(&self, keys: &[NodeKey]) -> Vec<CorpusId> {
+        let mut inputs: Vec<CorpusId> = keys.iter().flat_map(|key| self.inputs(key)).copied().collect();
+        inputs.sort_unstable();
+        inputs.dedup();
+        inputs
+    }
+
+    /// Number of nodes reached by any input.
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut index = CorpusIndex::new();
+        index.record(3, &[(1, 0), (2, 0), (1, 1)]);
+        index.record(1, &[(1, 0), (3, 0)]);
+        index.record(3, &[(1, 0)]);
+        assert_eq!(index.inputs(&(1, 0)), &[1, 3]);
+        assert_eq!(index.inputs(&(2, 0)), &[3]);
+        assert!(index.inputs(&(9, 0)).is_empty());
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.inputs_reaching(&[(2, 0), (3, 0), (9, 0)]), vec![1, 3]);
+    }
+}
@@ -0,0 +1,120 @@
+use std::io;
+use serde::{Deserialize, Serialize};
+use nodes::NodeKey;
+use packets::{self, invalid_data, PacketAssembler};
+
+/// Bumped whenever the layout of `DGraphDelta` changes.
+pub const DELTA_FORMAT_VERSION: u32 = 2;
+
+/// Changes made to a `DGraph` in generations `from..to`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DGraphDelta {
+    pub from: u64,
+    pub to: u64,
+    // the sender's whole graph rather than its changes, see
+    // `DGraph::full_delta_for`
+    pub full: bool,
+    // nodes created in the range
+    pub nodes: Vec<NodeKey>,
+    // edges created in the range
+    pub edges: Vec<(NodeKey, NodeKey)>,
+    // weight added to each node in the range
+    pub weights: Vec<(NodeKey, usize)>,
+}
+
+impl DGraphDelta {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.edges.is_empty() && self.weights.is_empty()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(&(DELTA_FORMAT_VERSION, self)).unwrap()
+    }
+
+    pub fn deserialize(bytes: Vec<u8>) -> Self {
+        Self::try_deserialize(bytes).expect("cannot deserialize DGraphDelta")
+    }
+
+    /// `deserialize` for bytes received from a peer.
+    pub fn try_deserialize(bytes: Vec<u8>) -> io::Result<Self> {
+        let version: u32 = bincode::deserialize(&bytes).map_err(invalid_data)?;
+        if version != DELTA_FORMAT_VERSION {
+            return Err(invalid_data(format!("unsupported DGraphDelta format version {}", version)));
+        }
+        let (_, delta): (u32, DGraphDelta) = bincode::deserialize(&bytes).map_err(invalid_data)?;
+        Ok(delta)
+    }
+
+    /// Encodes the delta as packets of at most `max_len` bytes each, to be
+    /// put back together by a `DeltaAssembler`. A delta has no size limit,
+    /// e.g. the first one sent to a rank carries the whole graph.
+    pub fn to_packets(&self, max_len: usize) -> Vec<Vec<u8>> {
+        packets::to_packets(&self.serialize(), self.to, max_len)
+    }
+}
+
+/// Puts together the deltas of one sender, see `packets::PacketAssembler`.
+#[derive(Default)]
+pub struct DeltaAssembler(PacketAssembler);
+
+impl DeltaAssembler {
+    pub fn new() -> Self {
+        DeltaAssembler::default()
+    }
+
+    pub fn push(&mut self, packet: &[u8]) -> io::Result<Option<DGraphDelta>> {
+        self.0.push(packet)?.map(DGraphDelta::try_deserialize).transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let delta = DGraphDelta {
+            from: 3,
+            to: 5,
+            full: false,
+            nodes: vec![(2, 1)],
+            edges: vec![((1, 0), (2, 1))],
+            weights: vec![((1, 0), 4), ((2, 1), 1)],
+        };
+        assert_eq!(DGraphDelta::deserialize(delta.serialize()), delta);
+    }
+
+    #[test]
+    fn test_packets_round_trip() {
+        let delta = DGraphDelta {
+            from: 0,
+            to: 9,
+            full: true,
+            nodes: (0..400).map(|i| (i, 0)).collect(),
+            edges: (1..400).map(|i| ((i - 1, 0), (i, 0))).collect(),
+            weights: (0..400).map(|i| ((i, 0), 1)).collect(),
+        };
+        let packets = delta.to_packets(4096 - 5);
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|p| p.len() <= 4096 - 5));
+
+        let mut assembler = DeltaAssembler::new();
+        let mut result = None;
+        for packet in &packets {
+            assert!(result.is_none());
+            result = assembler.push(packet).unwrap();
+        }
+        assert_eq!(result.unwrap(), delta);
+    }
+
+    #[test]
+    fn test_bad_delta_rejected() {
+        let mut bytes = DGraphDelta::default().serialize();
+        bytes[0] = 9;
+        assert!(DGraphDelta::try_deserialize(bytes).is_err());
+        assert!(DGraphDelta::try_deserialize(vec![2, 0, 0, 0, 1]).is_err());
+        // whole packets around bytes that are no delta
+        let packets = packets::to_packets(&[1, 2, 3], 1, 64);
+        assert!(DeltaAssembler::new().push(&packets[0]).is_err());
+    }
+}
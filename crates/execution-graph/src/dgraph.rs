@@ -1,55 +1,267 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::ops::Deref;
-use std::rc::Rc;
-use serde::ser::SerializeStruct;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::io;
 use serde::{Deserialize, Serialize};
-use partition::PartitionPlan;
-use crate::nodes::{RENode, ENode};
-use crate::{db, rr};
+use partition::{self, Multilevel, PartitionPlan, PartitionStrategy, Reassignment};
+use crate::corpus::CorpusIndex;
+use crate::delta::DGraphDelta;
+use crate::diff::{self, GraphDiff};
+use crate::dominator::{Bottleneck, DominatorTree};
+use crate::frontier::{self, FrontierNode};
+use crate::export;
+use crate::stats::{self, GraphStats};
+use crate::graph::ExecutionGraph;
+use crate::packets::invalid_data;
+use crate::path;
+use crate::nodes::{Arena, ENode, GraphView, NodeId, NodeKey};
 
+#[derive(Clone)]
 pub struct DGraph {
-    root: RENode,
-    available_nodes: HashMap<(u32, u8), RENode>,
-    _counter: usize
+    arena: Arena,
+    root: NodeId,
+    available_nodes: HashMap<NodeKey, NodeId>,
+    _counter: usize,
+    // change tracking for `delta_since` / `apply_delta`
+    generation: u64,
+    // generation each node was created in, indexed by `NodeId`
+    node_born: Vec<u64>,
+    // `(generation, parent, child)` in insertion order
+    edge_log: Vec<(u64, NodeId, NodeId)>,
+    // weight added to each node per generation, indexed by `NodeId`
+    weight_log: Vec<Vec<(u64, usize)>>,
+    // generations that replayed a delta received from the given peer
+    remote_generations: HashMap<u64, u32>,
+    // how weights fade at `end_epoch`
+    decay: WeightDecay,
+    // hits per replica of each node since the last `end_epoch`, sorted by
+    // replica, indexed by `NodeId`
+    epoch_weight: Vec<Vec<(u32, usize)>>,
+    // per past epoch, the hits of each node and replica in it (`WeightDecay::Window` only)
+    window: VecDeque<Vec<(NodeId, u32, usize)>>,
+    // most nodes kept before `compact` runs on its own
+    node_budget: Option<usize>,
+    // replica whose hits `add_trace` counts, usually the MPI rank
+    replica: u32,
+    // hits per replica of each node, sorted by replica, indexed by `NodeId`;
+    // a G-counter, so `merge` takes the per-replica maximum
+    counters: Vec<Vec<(u32, usize)>>,
 }
 
+// adds `hits` to the counter of `replica` in a list sorted by replica
+fn bump(counters: &mut Vec<(u32, usize)>, replica: u32, hits: usize) {
+    match counters.binary_search_by_key(&replica, |(r, _)| *r) {
+        Ok(i) => counters[i].1 += hits,
+        Err(i) => counters.insert(i, (replica, hits)),
+    }
+}
+
+// splits `lost` hits across `counters` in proportion to them, the remainder
+// going to the largest fractions, so the total decays as one weight would
+fn spread_loss(counters: &[(u32, usize)], lost: usize) -> Vec<(u32, usize)> {
+    let total: usize = counters.iter().map(|(_, hits)| hits).sum();
+    if total == 0 {
+        return vec![];
+    }
+    let mut shares: Vec<(u32, usize)> = counters.iter().map(|(replica, hits)| (*replica, hits * lost / total)).collect();
+    let mut remainder = lost - shares.iter().map(|(_, share)| share).sum::<usize>();
+    let mut order: Vec<usize> = (0..counters.len()).collect();
+    order.sort_by_key(|i| (std::cmp::Reverse(counters[*i].1 * lost % total), *i));
+    for i in order {
+        if remainder == 0 {
+            break;
+        }
+        shares[i].1 += 1;
+        remainder -= 1;
+    }
+    shares
+}
+
+/// Share of the node budget a budget-triggered `compact` shrinks the graph
+/// to, so it does not have to run again on the very next trace.
+const COMPACT_TARGET_PCT: usize = 75;
+
+/// How `DGraph::end_epoch` lets old hits fade, so partitioning follows where
+/// executions go now rather than the whole campaign history. Decay is local
+/// to a graph: deltas keep carrying the raw weight increments. It shrinks
+/// the per-replica counters along with the weight, so it survives `merge`
+/// and serialization.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum WeightDecay {
+    /// Weights only grow.
+    #[default]
+    None,
+    /// Every epoch keeps `percent` percent of each weight.
+    Exponential { percent: usize },
+    /// Weights count only what was added in the last `epochs` epochs.
+    Window { epochs: usize },
+}
+
+impl WeightDecay {
+    /// Parses `none`, `exp:<percent>` or `window:<epochs>`.
+    pub fn from_spec(spec: &str) -> Option<Self> {
+        let (kind, arg) = match spec.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg.parse::<usize>().ok()?)),
+            None => (spec, None),
+        };
+        match (kind, arg) {
+            ("none", None) => Some(WeightDecay::None),
+            ("exp", Some(percent)) if percent <= 100 => Some(WeightDecay::Exponential { percent }),
+            ("window", Some(epochs)) if epochs > 0 => Some(WeightDecay::Window { epochs }),
+            _ => None,
+        }
+    }
+}
+
+/// Bumped whenever the layout of `SerializableDGraph` changes, together
+/// with `snapshot::SNAPSHOT_FORMAT_VERSION`.
+pub const DGRAPH_FORMAT_VERSION: u32 = 2;
+
+/// On-wire form of a `DGraph`. `version` must stay the first field so a
+/// reader can check it before decoding the rest.
 #[derive(Serialize, Deserialize)]
 pub struct SerializableDGraph {
-    nodes: Vec<(u32, u8, usize)>,
-    edges: Vec<(u32, u32)>,
+    version: u32,
+    root: NodeKey,
+    nodes: Vec<ENode>,
+    edges: Vec<(NodeKey, NodeKey)>,
+    // hits per replica of every node, in the order of `nodes`
+    counters: Vec<Vec<(u32, usize)>>,
+}
+
+impl Default for DGraph {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl DGraph {
     pub fn new() -> Self {
-        let root = rr!(ENode::new_claimed(0, 0));
+        Self::with_root(0, 0)
+    }
+
+    pub fn with_root(idx: u32, nth: u8) -> Self {
+        let mut arena = Arena::new();
+        let root = arena.alloc(ENode::new_claimed(idx, nth));
         let mut dg = DGraph {
+            arena,
             root,
             available_nodes: HashMap::new(),
             _counter: u32::MAX as usize,
+            generation: 0,
+            node_born: vec![0],
+            edge_log: vec![],
+            weight_log: vec![vec![]],
+            remote_generations: HashMap::new(),
+            decay: WeightDecay::None,
+            epoch_weight: vec![vec![]],
+            window: VecDeque::new(),
+            node_budget: None,
+            replica: 0,
+            counters: vec![vec![]],
         };
-        dg.available_nodes.insert(
-            (db!(dg.root).idx, db!(dg.root).nth),
-            dg.root.clone(),
-        );
+        dg.available_nodes.insert((idx, nth), root);
         dg
     }
 
+    pub fn node(&self, id: NodeId) -> &ENode {
+        self.arena.get(id)
+    }
+
+    pub fn get(&self, key: &NodeKey) -> Option<&ENode> {
+        self.available_nodes.get(key).map(|id| self.arena.get(*id))
+    }
+
+    pub fn len(&self) -> usize {
+        self.available_nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.available_nodes.is_empty()
+    }
+
+    fn get_or_insert(&mut self, key: NodeKey) -> (NodeId, bool) {
+        match self.available_nodes.get(&key) {
+            Some(id) => (*id, false),
+            None => {
+                let mut node = ENode::new_claimed(key.0, key.1);
+                node.weight = 0;
+                let id = self.arena.alloc(node);
+                self.available_nodes.insert(key, id);
+                self.node_born.push(self.generation);
+                self.weight_log.push(vec![]);
+                self.epoch_weight.push(vec![]);
+                self.counters.push(vec![]);
+                (id, true)
+            }
+        }
+    }
+
+    fn add_weight(&mut self, id: NodeId, weight: usize) {
+        if weight == 0 {
+            return;
+        }
+        self.arena.get_mut(id).weight += weight;
+        let log = &mut self.weight_log[id];
+        match log.last_mut() {
+            Some((generation, added)) if *generation == self.generation => *added += weight,
+            _ => log.push((self.generation, weight)),
+        }
+    }
+
+    fn counter(&self, id: NodeId, replica: u32) -> usize {
+        let counters = &self.counters[id];
+        match counters.binary_search_by_key(&replica, |(r, _)| *r) {
+            Ok(i) => counters[i].1,
+            Err(_) => 0,
+        }
+    }
+
+    // `weight` hits of `replica` on `id`
+    fn count(&mut self, id: NodeId, replica: u32, weight: usize) {
+        if weight == 0 {
+            return;
+        }
+        bump(&mut self.counters[id], replica, weight);
+        bump(&mut self.epoch_weight[id], replica, weight);
+        self.add_weight(id, weight);
+    }
+
+    // takes back up to `weight` hits of `replica` on `id`
+    fn uncount(&mut self, id: NodeId, replica: u32, weight: usize) {
+        let counters = &mut self.counters[id];
+        if let Ok(i) = counters.binary_search_by_key(&replica, |(r, _)| *r) {
+            let lost = weight.min(counters[i].1);
+            counters[i].1 -= lost;
+            let node = self.arena.get_mut(id);
+            node.weight = node.weight.saturating_sub(lost);
+        }
+    }
+
+    fn add_edge(&mut self, parent: NodeId, child: NodeId) {
+        if !self.arena.get(parent).children.contains(&child) {
+            self.arena.add_child(parent, child);
+            self.edge_log.push((self.generation, parent, child));
+        }
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut nodes = vec![];
-        for (key, node) in &self.available_nodes {
-            nodes.push((db!(node).idx, db!(node).nth, db!(node).weight));
-        }
         let mut edges = vec![];
-        for (key, node) in &self.available_nodes {
-            for child in &db!(node).children {
-                edges.push((db!(node).idx, db!(child).idx));
+        let mut counters = vec![];
+        for (id, node) in self.arena.iter() {
+            nodes.push(node.clone());
+            counters.push(self.counters[id].clone());
+            for child in &node.children {
+                edges.push((node.key(), self.arena.get(*child).key()));
             }
         }
 
         let sdg = SerializableDGraph {
+            version: DGRAPH_FORMAT_VERSION,
+            root: self.arena.get(self.root).key(),
             nodes,
             edges,
+            counters,
         };
 
         bincode::serialize(&sdg).unwrap()
@@ -58,130 +270,982 @@ impl DGraph {
 
 
     pub fn deserialize(bytes: Vec<u8>) -> Self {
-        let sdg: SerializableDGraph = bincode::deserialize(&bytes).unwrap();
-        let mut dg = DGraph::new();
-        let mut nodes = HashMap::new();
-        for (idx, nth, weight) in sdg.nodes {
-            let node = rr!(ENode::new_claimed(idx, 0));
-            db!(mut, node).weight = weight;
-            db!(mut, node).nth = nth;
-            nodes.insert(idx, node.clone());
-            dg.available_nodes.insert((idx, nth), node);
-        }
-        for (idx, child_idx) in sdg.edges {
-            let node = nodes.get(&idx).unwrap();
-            let child = nodes.get(&child_idx).unwrap();
-            db!(mut, node).children.push(child.clone());
+        Self::try_deserialize(bytes).expect("cannot deserialize DGraph")
+    }
+
+    /// `deserialize` for bytes that may be damaged or of another format
+    /// version, e.g. read from disk or received from a peer.
+    pub fn try_deserialize(bytes: Vec<u8>) -> io::Result<Self> {
+        let version: u32 = bincode::deserialize(&bytes).map_err(invalid_data)?;
+        if version != DGRAPH_FORMAT_VERSION {
+            return Err(invalid_data(format!("unsupported DGraph format version {}", version)));
         }
-        dg
+
+        let sdg: SerializableDGraph = bincode::deserialize(&bytes).map_err(invalid_data)?;
+        if sdg.counters.len() != sdg.nodes.len() {
+            return Err(invalid_data("DGraph counters do not match its nodes"));
+        }
+        let mut dg = DGraph::with_root(sdg.root.0, sdg.root.1);
+        for (node, counters) in sdg.nodes.iter().zip(&sdg.counters) {
+            let (id, _) = dg.get_or_insert(node.key());
+            for (replica, hits) in counters {
+                dg.count(id, *replica, *hits);
+            }
+            // the root weighs something before any hit
+            dg.arena.get_mut(id).weight = node.weight;
+        }
+        for (parent, child) in sdg.edges {
+            match (dg.available_nodes.get(&parent), dg.available_nodes.get(&child)) {
+                (Some(parent), Some(child)) => {
+                    let (parent, child) = (*parent, *child);
+                    dg.add_edge(parent, child);
+                }
+                _ => return Err(invalid_data("DGraph edge between unknown nodes")),
+            }
+        }
+        Ok(dg)
+    }
+
+    pub fn stats(&self) -> GraphStats {
+        stats::graph_stats(self)
+    }
+
+    /// Nodes some `nth` variant of which went on to edges they never took.
+    pub fn frontier(&self, corpus: &CorpusIndex) -> Vec<FrontierNode> {
+        frontier::frontier(self, corpus)
+    }
+
+    pub fn dominators(&self) -> DominatorTree {
+        DominatorTree::new(self)
+    }
+
+    /// The `n` nodes gating the most weight, see `DominatorTree::bottlenecks`.
+    pub fn bottlenecks(&self, n: usize) -> Vec<Bottleneck> {
+        DominatorTree::new(self).bottlenecks(self, n)
+    }
+
+    /// Nodes, edges and weights that changed from `self` to `other`.
+    pub fn diff(&self, other: &DGraph) -> GraphDiff {
+        diff::diff(self, other)
+    }
+
+    /// Keys from the root to `key` along the fewest edges, see `path`.
+    pub fn shortest_path(&self, key: NodeKey) -> Option<Vec<NodeKey>> {
+        path::shortest_path(self, *self.available_nodes.get(&key)?)
+    }
+
+    /// Keys from the root to `key` along the most hit way there, see `path`.
+    pub fn heaviest_path(&self, key: NodeKey) -> Option<Vec<NodeKey>> {
+        path::heaviest_path(self, *self.available_nodes.get(&key)?)
     }
 
+    /// Rebuilds a graph from `export::to_json` output.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let graph = export::parse_json(json)?;
+        let positions = graph.positions();
+        let root = &graph.nodes[positions[&graph.root]];
+        let mut dg = DGraph::with_root(root.idx, root.nth);
+        for node in &graph.nodes {
+            let (id, inserted) = dg.get_or_insert((node.idx, node.nth));
+            // exports of graphs without counters credit all hits to our replica
+            if inserted && node.counters.is_empty() {
+                dg.count(id, dg.replica, node.weight);
+            }
+            for (replica, hits) in &node.counters {
+                dg.count(id, *replica, *hits);
+            }
+            dg.arena.get_mut(id).weight = node.weight;
+        }
+        for node in &graph.nodes {
+            let parent = dg.available_nodes[&(node.idx, node.nth)];
+            for child in &node.children {
+                let child = &graph.nodes[positions[child]];
+                let child = dg.available_nodes[&(child.idx, child.nth)];
+                dg.add_edge(parent, child);
+            }
+        }
+        Ok(dg)
+    }
+
+    /// Sets the replica `add_trace` counts hits for. Graphs that are merged
+    /// with each other need distinct replicas.
+    pub fn set_replica(&mut self, replica: u32) {
+        self.replica = replica;
+    }
+
+    /// Copies the nodes and edges of `other` into this graph. Weights are
+    /// per-replica counters of which the larger one wins, so merging is
+    /// commutative, associative and idempotent: graphs can be merged in any
+    /// order, and more than once, with the same result.
     pub fn merge(&mut self, other: &DGraph) {
-        for (key, node) in &other.available_nodes {
-            if !self.available_nodes.contains_key(key) {
-                self.available_nodes.insert(key.clone(), node.clone());
-            } else {
-                let self_node = self.available_nodes.get(key).unwrap();
-                db!(mut, self_node).weight += db!(node).weight;
-
-                for child in &db!(node).children {
-                    let mut is_child_exist = false;
-                    for self_child in &db!(self_node).children {
-                        if db!(self_child).idx == db!(child).idx {
-                            is_child_exist = true;
-                            break;
-                        }
-                    }
-                    if !is_child_exist {
-                        db!(mut, self_node).children.push(child.clone());
-                    }
+        for (other_id, other_node) in other.arena.iter() {
+            let (self_id, _) = self.get_or_insert(other_node.key());
+            for (replica, hits) in &other.counters[other_id] {
+                let known = self.counter(self_id, *replica);
+                if *hits > known {
+                    self.count(self_id, *replica, hits - known);
                 }
             }
+
+            for child in &other_node.children {
+                let child_key = other.arena.get(*child).key();
+                let (self_child, _) = self.get_or_insert(child_key);
+                self.add_edge(self_id, self_child);
+            }
+        }
+        self.enforce_budget();
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Everything that changed in generations `since..` and starts a new
+    /// generation, so the returned `to` is the `since` of the next call.
+    pub fn delta_since(&mut self, since: u64) -> DGraphDelta {
+        self.collect_delta(since, None)
+    }
+
+    /// Like `delta_since`, but leaves out changes that were applied from
+    /// `peer`'s own deltas, so they are not echoed back to it.
+    pub fn delta_for(&mut self, since: u64, peer: u32) -> DGraphDelta {
+        self.collect_delta(since, Some(peer))
+    }
+
+    fn collect_delta(&mut self, since: u64, peer: Option<u32>) -> DGraphDelta {
+        let remote_generations = &self.remote_generations;
+        let wanted = |generation: u64| {
+            generation >= since
+                && (peer.is_none() || remote_generations.get(&generation) != peer.as_ref())
+        };
+
+        // the root exists in every graph, so it is never sent as a new node
+        let first_new = self.node_born.partition_point(|born| *born < since);
+        let nodes = (first_new..self.node_born.len())
+            .filter(|id| *id != self.root && wanted(self.node_born[*id]))
+            .map(|id| self.arena.get(id).key())
+            .collect();
+
+        let first_edge = self.edge_log.partition_point(|(generation, _, _)| *generation < since);
+        let edges = self.edge_log[first_edge..].iter()
+            .filter(|(generation, _, _)| wanted(*generation))
+            .map(|(_, parent, child)| (self.arena.get(*parent).key(), self.arena.get(*child).key()))
+            .collect();
+
+        let mut weights = vec![];
+        for (id, log) in self.weight_log.iter().enumerate() {
+            let added: usize = log.iter()
+                .filter(|(generation, _)| wanted(*generation))
+                .map(|(_, added)| added)
+                .sum();
+            if added > 0 {
+                weights.push((self.arena.get(id).key(), added));
+            }
+        }
+
+        self.generation += 1;
+        DGraphDelta {
+            from: since,
+            to: self.generation,
+            full: false,
+            nodes,
+            edges,
+            weights,
+        }
+    }
+
+    /// The whole graph as one delta from generation 0, for a `peer` that
+    /// has to catch up from scratch once the history it missed is compacted:
+    /// every node and edge, and the hits of every replica but `peer`'s own.
+    /// Starts a new generation like `delta_for`.
+    pub fn full_delta_for(&mut self, peer: u32) -> DGraphDelta {
+        let mut nodes = vec![];
+        let mut edges = vec![];
+        let mut weights = vec![];
+        for (id, node) in self.arena.iter() {
+            if id != self.root {
+                nodes.push(node.key());
+            }
+            for child in &node.children {
+                edges.push((node.key(), self.arena.get(*child).key()));
+            }
+            let hits: usize = self.counters[id].iter()
+                .filter(|(replica, _)| *replica != peer)
+                .map(|(_, hits)| hits)
+                .sum();
+            if hits > 0 {
+                weights.push((node.key(), hits));
+            }
+        }
+
+        self.generation += 1;
+        DGraphDelta {
+            from: 0,
+            to: self.generation,
+            full: true,
+            nodes,
+            edges,
+            weights,
         }
     }
+
+    /// Replays a delta produced by `peer` in a generation of its own. Its
+    /// weight counts as hits of replica `peer`. A `full` delta holds all of
+    /// `peer`'s hits, so it only adds the ones not known yet and can be
+    /// applied again, e.g. when a peer is sent the full graph more than once.
+    /// Any other delta adds its weight as is, even one from generation 0.
+    pub fn apply_delta(&mut self, delta: &DGraphDelta, peer: u32) {
+        self.generation += 1;
+        self.remote_generations.insert(self.generation, peer);
+
+        for key in &delta.nodes {
+            self.get_or_insert(*key);
+        }
+        for (key, added) in &delta.weights {
+            let (id, _) = self.get_or_insert(*key);
+            let added = if delta.full { added.saturating_sub(self.counter(id, peer)) } else { *added };
+            self.count(id, peer, added);
+        }
+        for (parent, child) in &delta.edges {
+            let (parent, _) = self.get_or_insert(*parent);
+            let (child, _) = self.get_or_insert(*child);
+            self.add_edge(parent, child);
+        }
+
+        self.generation += 1;
+        self.enforce_budget();
+    }
+
+    /// Drops change history older than `acked`; deltas can no longer be
+    /// requested from before that generation.
+    pub fn compact_history(&mut self, acked: u64) {
+        let first_edge = self.edge_log.partition_point(|(generation, _, _)| *generation < acked);
+        self.edge_log.drain(..first_edge);
+        for log in &mut self.weight_log {
+            log.retain(|(generation, _)| *generation >= acked);
+        }
+        self.remote_generations.retain(|generation, _| *generation >= acked);
+    }
 }
 
 impl DGraph {
-    pub fn add_trace(&mut self, mut trace: Vec<(u32, u8)>) {
-        let last: Option<RENode> = None;
-        for (idx, nth) in trace {
-            if !self.available_nodes.contains_key(&(idx, nth)) {
-                let new_node = rr!(ENode::new_claimed(idx, nth));
-                self.available_nodes.insert((idx, nth), new_node.clone());
-                db!(mut, last.clone().unwrap()).children.push(new_node);
-            } else {
-                let node = self.available_nodes.get(&(idx, nth)).unwrap();
-                db!(mut, last.clone().unwrap()).weight += 1;
+    pub fn add_trace(&mut self, trace: Vec<NodeKey>) {
+        let mut last = self.root;
+        for key in trace {
+            let (id, _) = self.get_or_insert(key);
+            self.count(id, self.replica, 1);
+            self.add_edge(last, id);
+            last = id;
+        }
+        self.enforce_budget();
+    }
+
+    /// Bounds the graph to `max_nodes` nodes: whenever a trace, delta or
+    /// merge grows it past that, it is compacted to below the budget.
+    pub fn set_node_budget(&mut self, max_nodes: Option<usize>) {
+        self.node_budget = max_nodes;
+        self.enforce_budget();
+    }
+
+    fn enforce_budget(&mut self) {
+        if let Some(budget) = self.node_budget {
+            if self.len() > budget {
+                self.compact(budget * COMPACT_TARGET_PCT / 100);
             }
         }
     }
 
-    pub fn partition(&mut self, k: usize) -> Vec<PartitionPlan> {
-        // pick k partitions from the graph
-        let partition_size = self.available_nodes.len() / k;
+    /// Shrinks the graph to at most `max_nodes` nodes (never below the
+    /// root) and returns how many were removed. First the lightest
+    /// `nth > 0` variants are folded into their `nth = 0` node, which takes
+    /// over their weight and edges. If that is not enough, the coldest
+    /// leaves are dropped. Every remaining node stays reachable from
+    /// whatever reached it before.
+    ///
+    /// Compaction is local: deltas report the weight of folded variants on
+    /// their `nth = 0` node, and peers keep whatever they already had.
+    pub fn compact(&mut self, max_nodes: usize) -> usize {
+        let before = self.len();
+        if before <= max_nodes {
+            return 0;
+        }
+        let mut excess = before - max_nodes;
 
-        let mut partitions = vec![];
+        // representative of every node; folded nodes point at their nth = 0 node
+        let mut rep: Vec<Option<NodeId>> = (0..self.arena.len()).map(Some).collect();
+        let mut variants: Vec<(usize, NodeKey, NodeId, NodeId)> = self.available_nodes.iter()
+            .filter(|(key, id)| key.1 > 0 && **id != self.root)
+            .filter_map(|(key, id)| {
+                let base = *self.available_nodes.get(&(key.0, 0))?;
+                Some((self.arena.get(*id).weight, *key, *id, base))
+            })
+            .collect();
+        variants.sort();
+        for (_, _, id, base) in variants.into_iter().take(excess) {
+            rep[id] = Some(base);
+            excess -= 1;
+        }
 
-        let mut current_partition = vec![];
-        let mut current_weight = 0;
+        // the folded graph, in terms of representatives
+        let mut weight = vec![0; self.arena.len()];
+        let mut children: Vec<HashSet<NodeId>> = vec![HashSet::new(); self.arena.len()];
+        for (id, node) in self.arena.iter() {
+            let from = rep[id].unwrap();
+            weight[from] += node.weight;
+            for child in &node.children {
+                let to = rep[*child].unwrap();
+                if to != from {
+                    children[from].insert(to);
+                }
+            }
+        }
 
-        for (key, node) in &self.available_nodes {
-            current_weight += db!(node).weight;
-            current_partition.push(node.clone());
-            if current_partition.len() == partition_size {
-                partitions.push(PartitionPlan {
-                    plan: current_partition,
-                    weight: current_weight,
-                    dependencies: Default::default(),
-                });
-                current_partition = vec![];
-                current_weight = 0;
+        if excess > 0 {
+            let mut parents: Vec<Vec<NodeId>> = vec![vec![]; self.arena.len()];
+            for (id, targets) in children.iter().enumerate() {
+                for child in targets {
+                    parents[*child].push(id);
+                }
+            }
+            let mut out_degree: Vec<usize> = children.iter().map(|c| c.len()).collect();
+            let mut leaves: BinaryHeap<Reverse<(usize, NodeKey, NodeId)>> = (0..self.arena.len())
+                .filter(|id| rep[*id] == Some(*id) && out_degree[*id] == 0 && *id != self.root)
+                .map(|id| Reverse((weight[id], self.arena.get(id).key(), id)))
+                .collect();
+            while excess > 0 {
+                let Some(Reverse((_, _, leaf))) = leaves.pop() else {
+                    break;
+                };
+                rep[leaf] = None;
+                excess -= 1;
+                for parent in &parents[leaf] {
+                    out_degree[*parent] -= 1;
+                    if out_degree[*parent] == 0 && *parent != self.root && rep[*parent] == Some(*parent) {
+                        leaves.push(Reverse((weight[*parent], self.arena.get(*parent).key(), *parent)));
+                    }
+                }
+            }
+            // nodes folded into a dropped node go with it
+            let dropped: Vec<bool> = rep.iter().enumerate().map(|(id, r)| *r != Some(id)).collect();
+            for r in &mut rep {
+                *r = r.filter(|r| !dropped[*r]);
             }
         }
-        partitions
+
+        self.rebuild(&rep);
+        before - self.len()
     }
 
+    // keeps the nodes that are their own representative, renumbered in
+    // arena order, and moves everything of the others onto their representative
+    fn rebuild(&mut self, rep: &[Option<NodeId>]) {
+        let mut new_id = vec![None; rep.len()];
+        let mut arena = Arena::new();
+        let mut node_born = vec![];
+        for (id, node) in self.arena.iter() {
+            if rep[id] == Some(id) {
+                let mut kept = node.clone();
+                kept.weight = 0;
+                kept.children.clear();
+                new_id[id] = Some(arena.alloc(kept));
+                node_born.push(self.node_born[id]);
+            }
+        }
+        let map = |id: NodeId| rep[id].and_then(|r| new_id[r]);
+
+        let mut weight_log: Vec<Vec<(u64, usize)>> = vec![vec![]; arena.len()];
+        let mut epoch_weight: Vec<Vec<(u32, usize)>> = vec![vec![]; arena.len()];
+        let mut counters: Vec<Vec<(u32, usize)>> = vec![vec![]; arena.len()];
+        for (id, node) in self.arena.iter() {
+            let Some(to) = map(id) else {
+                continue;
+            };
+            arena.get_mut(to).weight += node.weight;
+            for (replica, hits) in &self.epoch_weight[id] {
+                bump(&mut epoch_weight[to], *replica, *hits);
+            }
+            for (replica, hits) in &self.counters[id] {
+                bump(&mut counters[to], *replica, *hits);
+            }
+            weight_log[to].extend_from_slice(&self.weight_log[id]);
+            for child in &node.children {
+                if let Some(child) = map(*child) {
+                    if child != to {
+                        arena.add_child(to, child);
+                    }
+                }
+            }
+        }
+        for log in &mut weight_log {
+            log.sort();
+        }
+
+        self.edge_log = self.edge_log.iter()
+            .filter_map(|(generation, parent, child)| Some((*generation, map(*parent)?, map(*child)?)))
+            .filter(|(_, parent, child)| parent != child)
+            .collect();
+        for epoch in &mut self.window {
+            *epoch = epoch.iter().filter_map(|(id, replica, hits)| Some((map(*id)?, *replica, *hits))).collect();
+        }
+        self.available_nodes = arena.iter().map(|(id, node)| (node.key(), id)).collect();
+        self.root = new_id[self.root].unwrap();
+        self.arena = arena;
+        self.node_born = node_born;
+        self.weight_log = weight_log;
+        self.epoch_weight = epoch_weight;
+        self.counters = counters;
+    }
+
+    pub fn set_decay(&mut self, decay: WeightDecay) {
+        self.decay = decay;
+        self.window.clear();
+    }
+
+    /// Closes the current epoch and lets weights decay as configured with
+    /// `set_decay`.
+    pub fn end_epoch(&mut self) {
+        let gained: Vec<(NodeId, u32, usize)> = self.epoch_weight.iter_mut()
+            .enumerate()
+            .flat_map(|(id, hits)| std::mem::take(hits).into_iter().map(move |(replica, hits)| (id, replica, hits)))
+            .collect();
+        match self.decay {
+            WeightDecay::None => {}
+            WeightDecay::Exponential { percent } => {
+                for id in 0..self.arena.len() {
+                    let total: usize = self.counters[id].iter().map(|(_, hits)| hits).sum();
+                    let lost = total - total * percent / 100;
+                    for (replica, hits) in spread_loss(&self.counters[id], lost) {
+                        self.uncount(id, replica, hits);
+                    }
+                }
+            }
+            WeightDecay::Window { epochs } => {
+                self.window.push_back(gained);
+                while self.window.len() > epochs {
+                    for (id, replica, hits) in self.window.pop_front().unwrap() {
+                        self.uncount(id, replica, hits);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Splits the graph into exactly `k` plans (some may be empty when there
+    /// are fewer nodes than parts), balancing node weight and minimizing the
+    /// edges that cross plans.
+    pub fn partition(&mut self, k: usize) -> Vec<PartitionPlan> {
+        Multilevel.partition(self, k)
+    }
+
+    /// Partitions the graph starting from the `previous` plans, keeping
+    /// nodes on their owner unless the heaviest plan is more than
+    /// `max_imbalance_pct` percent above average. See `partition::reassign`.
+    pub fn repartition(&self, previous: &[PartitionPlan], k: usize,
+                       max_imbalance_pct: usize) -> Reassignment {
+        partition::reassign(self, previous, k, max_imbalance_pct)
+    }
+
+    /// `repartition` for a changing set of workers, see
+    /// `partition::reassign_workers`.
+    pub fn repartition_workers(&self, previous: &[PartitionPlan], previous_workers: &[u32],
+                               workers: &[u32], max_imbalance_pct: usize) -> Reassignment {
+        let units = vec![None; self.arena.len()];
+        partition::reassign_workers(self, previous, previous_workers, workers, max_imbalance_pct, &units)
+    }
+
+}
+
+impl GraphView for DGraph {
+    fn root(&self) -> NodeId {
+        self.root
+    }
+
+    fn arena(&self) -> &Arena {
+        &self.arena
+    }
+
+    fn counters(&self, id: NodeId) -> &[(u32, usize)] {
+        &self.counters[id]
+    }
+}
+
+impl ExecutionGraph for DGraph {
+    fn add_trace(&mut self, trace: Vec<NodeKey>) {
+        DGraph::add_trace(self, trace)
+    }
+
+    fn merge(&mut self, other: &DGraph) {
+        DGraph::merge(self, other)
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        DGraph::serialize(self)
+    }
+
+    fn deserialize(bytes: Vec<u8>) -> Self {
+        DGraph::deserialize(bytes)
+    }
+
+    fn partition(&mut self, k: usize) -> Vec<PartitionPlan> {
+        DGraph::partition(self, k)
+    }
+}
+
+impl PartialEq for DGraph {
+    // structural equality on `(idx, nth)` keys; arena layout and child order are ignored
+    fn eq(&self, other: &DGraph) -> bool {
+        if self.node(self.root).key() != other.node(other.root).key()
+            || self.available_nodes.len() != other.available_nodes.len() {
+            return false;
+        }
+        self.available_nodes.iter().all(|(key, id)| {
+            let other_node = match other.get(key) {
+                Some(node) => node,
+                None => return false,
+            };
+            let node = self.arena.get(*id);
+            let mut children: Vec<NodeKey> = node.children.iter()
+                .map(|c| self.arena.get(*c).key()).collect();
+            let mut other_children: Vec<NodeKey> = other_node.children.iter()
+                .map(|c| other.arena.get(*c).key()).collect();
+            children.sort_unstable();
+            other_children.sort_unstable();
+            node.weight == other_node.weight && children == other_children
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn is_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        is_send_sync::<DGraph>();
+        is_send_sync::<PartitionPlan>();
+    }
+
     #[test]
     fn test_insert() {
         // 1 -> 2
         //   -> 3 -> 4
         //        -> 5
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0)]);
+        graph.add_trace(vec![(1, 0), (3, 0), (4, 0)]);
+        graph.add_trace(vec![(1, 0), (3, 0), (5, 0)]);
 
-        macro_rules! create_enode {
-            ($idx: expr) => {
-                ENode {
-                    idx: $idx,
-                    nth: 0,
-                    weight: 1,
-                    _cumulated: 0,
-                    _claimed: false,
-                    children: vec![],
-                }
-            };
+        assert_eq!(graph.len(), 6);
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 3);
+        assert_eq!(graph.get(&(3, 0)).unwrap().weight, 2);
+        assert_eq!(graph.get(&(3, 0)).unwrap().children.len(), 2);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut a = DGraph::new();
+        a.add_trace(vec![(1, 0), (2, 0)]);
+        let mut b = DGraph::new();
+        b.set_replica(1);
+        b.add_trace(vec![(1, 0), (3, 0)]);
+
+        a.merge(&b);
+        assert_eq!(a.len(), 4);
+        assert_eq!(a.get(&(1, 0)).unwrap().weight, 2);
+        assert_eq!(a.get(&(1, 0)).unwrap().children.len(), 2);
+        // the other graph is left untouched
+        assert_eq!(b.get(&(1, 0)).unwrap().weight, 1);
+
+        // merging what is already known changes nothing
+        a.merge(&b);
+        assert_eq!(a.get(&(1, 0)).unwrap().weight, 2);
+        b.add_trace(vec![(1, 0)]);
+        a.merge(&b);
+        assert_eq!(a.get(&(1, 0)).unwrap().weight, 3);
+    }
+
+    fn replica_graph(replica: u32, traces: &[Vec<NodeKey>]) -> DGraph {
+        let mut graph = DGraph::new();
+        graph.set_replica(replica);
+        for trace in traces {
+            graph.add_trace(trace.clone());
+        }
+        graph
+    }
+
+    fn merged(graphs: &[&DGraph]) -> DGraph {
+        let mut result = DGraph::new();
+        for graph in graphs {
+            result.merge(graph);
+        }
+        result
+    }
+
+    fn traces() -> impl proptest::strategy::Strategy<Value = Vec<Vec<NodeKey>>> {
+        proptest::collection::vec(proptest::collection::vec((0u32..6, 0u8..3), 0..6), 0..6)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_merge_commutative(a in traces(), b in traces(), c in traces()) {
+            let (a, b, c) = (replica_graph(1, &a), replica_graph(2, &b), replica_graph(3, &c));
+            let abc = merged(&[&a, &b, &c]);
+            prop_assert!(abc == merged(&[&c, &a, &b]));
+            prop_assert!(abc == merged(&[&b, &c, &a]));
+        }
+
+        #[test]
+        fn prop_merge_associative(a in traces(), b in traces(), c in traces()) {
+            let (a, b, c) = (replica_graph(1, &a), replica_graph(2, &b), replica_graph(3, &c));
+            let ab = merged(&[&a, &b]);
+            let bc = merged(&[&b, &c]);
+            prop_assert!(merged(&[&ab, &c]) == merged(&[&a, &bc]));
+        }
+
+        #[test]
+        fn prop_merge_idempotent(a in traces(), b in traces()) {
+            let (a, b) = (replica_graph(1, &a), replica_graph(2, &b));
+            let ab = merged(&[&a, &b]);
+            prop_assert!(merged(&[&ab, &a, &b, &ab]) == ab);
+        }
+
+        #[test]
+        fn prop_merge_after_round_trip(a in traces(), b in traces()) {
+            let (a, b) = (replica_graph(1, &a), replica_graph(2, &b));
+            let (a2, b2) = (DGraph::deserialize(a.serialize()), DGraph::deserialize(b.serialize()));
+            let json = DGraph::from_json(&export::to_json(&a, &[])).unwrap();
+            let ab = merged(&[&a, &b]);
+            prop_assert!(merged(&[&a2, &b2]) == ab);
+            prop_assert!(merged(&[&json, &b2]) == ab);
+            prop_assert!(merged(&[&DGraph::deserialize(ab.serialize()), &a, &b]) == ab);
+        }
+
+        #[test]
+        fn prop_merge_copies(traces in traces()) {
+            let mut a = replica_graph(1, &traces);
+            let mut result = merged(&[&a]);
+            let original = a.get(&(0, 0)).map(|node| node.weight);
+            // later updates on either side stay on that side
+            a.add_trace(vec![(0, 0), (7, 0)]);
+            result.add_trace(vec![(8, 0)]);
+            prop_assert_eq!(result.get(&(0, 0)).map(|node| node.weight), original);
+            prop_assert!(result.get(&(7, 0)).is_none());
+            prop_assert!(a.get(&(8, 0)).is_none());
+        }
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        // the same edge hit several times yields distinct nth nodes
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0), (1, 1), (2, 1)]);
+        graph.add_trace(vec![(1, 0), (3, 0), (1, 1), (3, 1)]);
+
+        let decoded = DGraph::deserialize(graph.serialize());
+        assert!(decoded == graph);
+        assert_eq!(decoded.get(&(1, 1)).unwrap().weight, 2);
+        let children: Vec<NodeKey> = decoded.get(&(1, 1)).unwrap().children.iter()
+            .map(|c| decoded.node(*c).key()).collect();
+        assert_eq!(children, vec![(2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn test_serialize_preserves_root() {
+        let mut graph = DGraph::with_root(9, 2);
+        graph.add_trace(vec![(1, 0)]);
+
+        let decoded = DGraph::deserialize(graph.serialize());
+        assert_eq!(decoded.node(decoded.root()).key(), (9, 2));
+        assert!(decoded == graph);
+    }
+
+    #[test]
+    #[should_panic(expected = "unsupported DGraph format version")]
+    fn test_deserialize_rejects_unknown_version() {
+        let mut bytes = DGraph::new().serialize();
+        bytes[0] = bytes[0].wrapping_add(1);
+        DGraph::deserialize(bytes);
+    }
+
+    #[test]
+    fn test_delta_incremental() {
+        let mut worker = DGraph::new();
+        let mut coordinator = DGraph::new();
+
+        worker.add_trace(vec![(1, 0), (2, 0)]);
+        let first = worker.delta_since(0);
+        coordinator.apply_delta(&first, 1);
+        assert!(coordinator == worker);
+
+        worker.add_trace(vec![(1, 0), (3, 0)]);
+        let second = worker.delta_since(first.to);
+        assert_eq!(second.from, first.to);
+        assert_eq!(second.nodes, vec![(3, 0)]);
+        assert_eq!(second.edges, vec![((1, 0), (3, 0))]);
+        assert_eq!(second.weights.len(), 2);
+        coordinator.apply_delta(&second, 1);
+        assert!(coordinator == worker);
+
+        assert!(worker.delta_since(second.to).is_empty());
+    }
+
+    #[test]
+    fn test_delta_not_echoed() {
+        let mut a = DGraph::new();
+        a.set_replica(1);
+        let mut b = DGraph::new();
+        b.set_replica(2);
+        let mut coordinator = DGraph::new();
+        a.add_trace(vec![(1, 0), (2, 0)]);
+        b.add_trace(vec![(1, 0), (3, 0)]);
+
+        coordinator.apply_delta(&a.delta_since(0), 1);
+        coordinator.apply_delta(&b.delta_since(0), 2);
+
+        let to_a = coordinator.delta_for(0, 1);
+        assert_eq!(to_a.nodes, vec![(3, 0)]);
+        assert_eq!(to_a.weights, vec![((1, 0), 1), ((3, 0), 1)]);
+        a.apply_delta(&to_a, 0);
+        assert!(a == coordinator);
+
+        // what a learned from the coordinator does not travel back
+        assert!(a.delta_for(a.generation(), 0).is_empty());
+        a.add_trace(vec![(1, 0), (3, 0)]);
+        let back = a.delta_for(0, 0);
+        assert!(back.nodes.iter().all(|key| *key != (3, 0)));
+        assert_eq!(back.weights.iter().find(|(key, _)| *key == (3, 0)).unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_compact_history() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0)]);
+        let first = graph.delta_since(0);
+        graph.add_trace(vec![(1, 0), (3, 0)]);
+        graph.compact_history(first.to);
+
+        let second = graph.delta_since(first.to);
+        assert_eq!(second.nodes, vec![(3, 0)]);
+        assert_eq!(second.edges, vec![((1, 0), (3, 0))]);
+    }
+
+    #[test]
+    fn test_full_delta_after_compaction() {
+        let mut worker = DGraph::new();
+        worker.set_replica(1);
+        let mut coordinator = DGraph::new();
+        worker.add_trace(vec![(1, 0), (2, 0)]);
+        coordinator.apply_delta(&worker.delta_since(0), 1);
+        let reply = coordinator.delta_for(0, 1);
+        worker.apply_delta(&reply, 0);
+
+        // the worker went silent while others moved on and history was dropped
+        let mut other = DGraph::new();
+        other.set_replica(2);
+        other.add_trace(vec![(1, 0), (3, 0)]);
+        other.add_trace(vec![(1, 0), (2, 0)]);
+        coordinator.apply_delta(&other.delta_since(0), 2);
+        coordinator.compact_history(coordinator.generation());
+        assert!(coordinator.delta_for(reply.to, 1).edges.is_empty());
+
+        let full = coordinator.full_delta_for(1);
+        worker.apply_delta(&full, 0);
+        assert!(worker == coordinator);
+        // sending it twice counts nothing twice
+        worker.apply_delta(&full, 0);
+        assert!(worker == coordinator);
+        assert_eq!(worker.get(&(2, 0)).unwrap().weight, 2);
+    }
+
+    #[test]
+    fn test_restarted_worker_adds_hits() {
+        let mut worker = DGraph::new();
+        worker.set_replica(1);
+        for _ in 0..5 {
+            worker.add_trace(vec![(1, 0)]);
+        }
+        let mut coordinator = DGraph::new();
+        coordinator.apply_delta(&worker.delta_since(0), 1);
+        // e.g. resumed from a snapshot: the worker starts over from generation 0
+        let mut coordinator = DGraph::deserialize(coordinator.serialize());
+        let mut worker = DGraph::new();
+        worker.set_replica(1);
+        for _ in 0..3 {
+            worker.add_trace(vec![(1, 0)]);
+        }
+        let delta = worker.delta_since(0);
+        assert!(!delta.full);
+        coordinator.apply_delta(&delta, 1);
+        assert_eq!(coordinator.get(&(1, 0)).unwrap().weight, 8);
+    }
+
+    #[test]
+    fn test_decay_exponential() {
+        let mut graph = DGraph::new();
+        graph.set_decay(WeightDecay::Exponential { percent: 50 });
+        for _ in 0..4 {
+            graph.add_trace(vec![(1, 0)]);
         }
+        graph.end_epoch();
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 2);
+        graph.add_trace(vec![(2, 0)]);
+        graph.end_epoch();
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 1);
+        assert_eq!(graph.get(&(2, 0)).unwrap().weight, 0);
+        // the delta still carries the raw hits
+        let delta = graph.delta_since(0);
+        assert!(delta.weights.contains(&((1, 0), 4)));
+    }
+
+    #[test]
+    fn test_decay_window() {
+        let mut graph = DGraph::new();
+        graph.set_decay(WeightDecay::Window { epochs: 2 });
+        graph.add_trace(vec![(1, 0)]);
+        graph.add_trace(vec![(1, 0)]);
+        graph.end_epoch();
+        graph.add_trace(vec![(1, 0), (2, 0)]);
+        graph.end_epoch();
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 3);
+        graph.end_epoch();
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 1);
+        assert_eq!(graph.get(&(2, 0)).unwrap().weight, 1);
+        graph.end_epoch();
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 0);
+        assert!(graph.get(&(2, 0)).is_some());
+    }
 
-        let node1 = rr!(create_enode!(1));
-        let mut tree = DGraph::new(node1);
-        tree.add_trace(vec![(1, 0), (2, 0)]);
-        tree.add_trace(vec![(1, 0), (3, 0), (4, 0)]);
-        tree.add_trace(vec![(1, 0), (3, 0), (5, 0)]);
+    #[test]
+    fn test_decay_many_replicas() {
+        let mut graph = DGraph::new();
+        graph.set_decay(WeightDecay::Exponential { percent: 90 });
+        for replica in 1..=10 {
+            let mut other = DGraph::new();
+            other.set_replica(replica);
+            other.add_trace(vec![(1, 0)]);
+            graph.merge(&other);
+        }
+        graph.end_epoch();
+        // one hit of a single replica is lost, not one of each
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 9);
+        graph.end_epoch();
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 8);
+        let copy = DGraph::deserialize(graph.serialize());
+        let mut merged = graph.clone();
+        merged.merge(&copy);
+        assert_eq!(merged.get(&(1, 0)).unwrap().weight, 8);
+    }
 
-        // dfs print
-        fn dfs_print(node: &RENode, depth: usize) {
-            let node_ref = db!(node);
-            println!("{}{}: {}", " ".repeat(depth * 2), node_ref.idx, node_ref._cumulated);
-            for child in &node_ref.children {
-                dfs_print(child, depth + 1);
+    #[test]
+    fn test_decay_survives_merge() {
+        let mut graph = DGraph::new();
+        graph.set_replica(1);
+        graph.set_decay(WeightDecay::Exponential { percent: 50 });
+        for _ in 0..4 {
+            graph.add_trace(vec![(1, 0)]);
+        }
+        graph.end_epoch();
+
+        // the counters decayed too, so neither a merge nor a round trip
+        // brings the old hits back
+        let mut merged = graph.clone();
+        merged.merge(&DGraph::deserialize(graph.serialize()));
+        assert_eq!(merged.get(&(1, 0)).unwrap().weight, 2);
+        let mut other = DGraph::new();
+        other.set_replica(2);
+        other.add_trace(vec![(1, 0)]);
+        merged.merge(&other);
+        assert_eq!(merged.get(&(1, 0)).unwrap().weight, 3);
+    }
+
+    #[test]
+    fn test_decay_from_spec() {
+        assert_eq!(WeightDecay::from_spec("none"), Some(WeightDecay::None));
+        assert_eq!(WeightDecay::from_spec("exp:90"), Some(WeightDecay::Exponential { percent: 90 }));
+        assert_eq!(WeightDecay::from_spec("window:8"), Some(WeightDecay::Window { epochs: 8 }));
+        assert_eq!(WeightDecay::from_spec("exp:120"), None);
+        assert_eq!(WeightDecay::from_spec("window"), None);
+        assert_eq!(WeightDecay::from_spec("linear:3"), None);
+    }
+
+    fn reachable(graph: &DGraph) -> HashSet<NodeKey> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![graph.root];
+        while let Some(id) = stack.pop() {
+            if seen.insert(graph.node(id).key()) {
+                stack.extend(&graph.node(id).children);
             }
         }
+        seen
+    }
+
+    #[test]
+    fn test_compact_folds_variants() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0), (1, 1), (2, 1), (3, 0)]);
+        graph.add_trace(vec![(1, 0), (2, 0), (4, 0)]);
+        assert_eq!(graph.compact(5), 2);
+
+        assert_eq!(graph.len(), 5);
+        assert!(graph.get(&(1, 1)).is_none() && graph.get(&(2, 1)).is_none());
+        assert_eq!(graph.get(&(1, 0)).unwrap().weight, 3);
+        assert_eq!(graph.get(&(2, 0)).unwrap().weight, 3);
+        // (2, 1) -> (3, 0) is now (2, 0) -> (3, 0)
+        assert_eq!(reachable(&graph).len(), 5);
+    }
+
+    #[test]
+    fn test_compact_drops_cold_leaves() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0), (3, 0)]);
+        graph.add_trace(vec![(1, 0), (2, 0), (3, 0)]);
+        graph.add_trace(vec![(1, 0), (4, 0), (5, 0)]);
+        assert_eq!(graph.compact(3), 3);
 
-        dfs_print(&tree.root, 0);
+        // (5, 0) and then (4, 0) went first, then the now-leaf (3, 0)
+        let keys: HashSet<NodeKey> = vec![(0, 0), (1, 0), (2, 0)].into_iter().collect();
+        assert_eq!(reachable(&graph), keys);
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph.compact(0), 2);
+        assert_eq!(graph.len(), 1);
+    }
+
+    #[test]
+    fn test_compact_keeps_deltas_consistent() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (1, 1), (2, 0)]);
+        graph.compact(3);
+
+        let mut peer = DGraph::new();
+        peer.apply_delta(&graph.delta_since(0), 1);
+        assert!(peer == graph);
+    }
+
+    #[test]
+    fn test_node_budget() {
+        let mut graph = DGraph::new();
+        graph.set_node_budget(Some(40));
+        for i in 0..50 {
+            let trace: Vec<NodeKey> = (0..=(i % 8) as u8).map(|nth| (1 + i % 5, nth)).collect();
+            graph.add_trace(trace);
+            assert!(graph.len() <= 40);
+        }
+        for idx in 1..6 {
+            assert!(graph.get(&(idx, 0)).is_some());
+        }
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0), (1, 1)]);
+        graph.add_trace(vec![(1, 0), (3, 0)]);
+        let plans = graph.partition(2);
+
+        let decoded = DGraph::from_json(&export::to_json(&graph, &plans)).unwrap();
+        assert!(decoded == graph);
     }
 
     #[test]
@@ -189,36 +1253,44 @@ mod tests {
         // 1 -> 2
         //   -> 3 -> 4
         //        -> 5
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0)]);
+        graph.add_trace(vec![(1, 0), (3, 0), (4, 0)]);
+        graph.add_trace(vec![(1, 0), (3, 0), (5, 0)]);
 
-        macro_rules! create_enode {
-            ($idx: expr) => {
-                ENode {
-                    idx: $idx,
-                    nth: 0,
-                    weight: 1,
-                    _cumulated: 0,
-                    _claimed: false,
-                    children: vec![],
-                }
-            };
-        }
-
-        let node1 = rr!(create_enode!(1));
-        let node2 = rr!(create_enode!(2));
-        let node3 = rr!(create_enode!(3));
-        let node4 = rr!(create_enode!(4));
-        let node5 = rr!(create_enode!(5));
-        db!(mut, node1).children.push(node2.clone());
-        db!(mut, node1).children.push(node3.clone());
-        db!(mut, node3).children.push(node4.clone());
-        db!(mut, node3).children.push(node5.clone());
-
+        let partitions = graph.partition(2);
+        assert_eq!(partitions.len(), 2);
+        // nothing is dropped
+        assert_eq!(partitions.iter().map(|p| p.plan.len()).sum::<usize>(), graph.len());
+    }
 
-        let mut tree = DGraph::new(node1);
+    #[test]
+    fn test_partition_follows_branches() {
+        // two independent subtrees below the root: a good bisection
+        // keeps each subtree whole and cuts at most one root edge
+        let mut graph = DGraph::new();
+        for i in 0..10 {
+            graph.add_trace(vec![(1, 0), (10 + i, 0), (30 + i, 0)]);
+            graph.add_trace(vec![(2, 0), (50 + i, 0), (70 + i, 0)]);
+        }
 
-        for i in tree.partition(2) {
-            println!("partition: {:?} with weight {:?}",
-                     i.plan.iter().map(|x| db!(x).idx).collect::<Vec<u32>>(), i.weight);
+        let partitions = graph.partition(2);
+        let owner = |idx: u32| partitions.iter().position(|p| p.plan.contains(&(idx, 0))).unwrap();
+        for i in 0..10 {
+            assert_eq!(owner(10 + i), owner(1));
+            assert_eq!(owner(30 + i), owner(1));
+            assert_eq!(owner(50 + i), owner(2));
+            assert_eq!(owner(70 + i), owner(2));
         }
+        assert_ne!(owner(1), owner(2));
+    }
+
+    #[test]
+    fn test_partition_more_parts_than_nodes() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0)]);
+        let partitions = graph.partition(4);
+        assert_eq!(partitions.len(), 4);
+        assert_eq!(partitions.iter().map(|p| p.plan.len()).sum::<usize>(), 2);
     }
 }
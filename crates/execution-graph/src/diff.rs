@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use delta::DGraphDelta;
+use nodes::{GraphView, NodeKey};
+
+/// What changed from one graph to another, by key, so keys have to be
+/// unique as they are in a `DGraph`. Everything is sorted by key.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GraphDiff {
+    // nodes only in the new graph
+    pub added_nodes: Vec<NodeKey>,
+    // nodes only in the old graph
+    pub removed_nodes: Vec<NodeKey>,
+    pub added_edges: Vec<(NodeKey, NodeKey)>,
+    pub removed_edges: Vec<(NodeKey, NodeKey)>,
+    // new minus old weight of every node whose weight differs, a node
+    // missing from one graph weighs 0 there
+    pub weights: Vec<(NodeKey, isize)>,
+}
+
+struct Contents {
+    weights: BTreeMap<NodeKey, usize>,
+    edges: BTreeSet<(NodeKey, NodeKey)>,
+}
+
+fn contents(graph: &dyn GraphView) -> Contents {
+    let arena = graph.arena();
+    let mut contents = Contents {
+        weights: BTreeMap::new(),
+        edges: BTreeSet::new(),
+    };
+    for (_, node) in arena.iter() {
+        contents.weights.insert(node.key(), node.weight);
+        for child in &node.children {
+            contents.edges.insert((node.key(), arena.get(*child).key()));
+        }
+    }
+    contents
+}
+
+/// Changes from `old` to `new`.
+pub fn diff(old: &dyn GraphView, new: &dyn GraphView) -> GraphDiff {
+    let (old, new) = (contents(old), contents(new));
+    let mut weights = vec![];
+    for (key, weight) in &new.weights {
+        let before = old.weights.get(key).copied().unwrap_or(0);
+        if *weight != before {
+            weights.push((*key, *weight as isize - before as isize));
+        }
+    }
+    for (key, weight) in &old.weights {
+        if !new.weights.contains_key(key) && *weight != 0 {
+            weights.push((*key, -(*weight as isize)));
+        }
+    }
+    weights.sort();
+
+    GraphDiff {
+        added_nodes: new.weights.keys().filter(|key| !old.weights.contains_key(key)).copied().collect(),
+        removed_nodes: old.weights.keys().filter(|key| !new.weights.contains_key(key)).copied().collect(),
+        added_edges: new.edges.difference(&old.edges).copied().collect(),
+        removed_edges: old.edges.difference(&new.edges).copied().collect(),
+        weights,
+    }
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty() && self.removed_nodes.is_empty() && self.added_edges.is_empty()
+            && self.removed_edges.is_empty() && self.weights.is_empty()
+    }
+
+    /// The delta bringing the old graph up to the new one, as far as a delta
+    /// can: removals and weight losses (compaction, decay) are left out.
+    /// Its weights are the hits gained, so each diff adds on to the last.
+    pub fn to_delta(&self) -> DGraphDelta {
+        DGraphDelta {
+            from: 0,
+            to: 0,
+            full: false,
+            nodes: self.added_nodes.clone(),
+            edges: self.added_edges.clone(),
+            weights: self.weights.iter()
+                .filter(|(_, delta)| *delta > 0)
+                .map(|(key, delta)| (*key, *delta as usize))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let gained: isize = self.weights.iter().map(|(_, delta)| *delta).filter(|d| *d > 0).sum();
+        write!(f, "+{} -{} nodes, +{} -{} edges, {} weights changed, {} hits gained",
+               self.added_nodes.len(), self.removed_nodes.len(), self.added_edges.len(),
+               self.removed_edges.len(), self.weights.len(), gained)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dgraph::DGraph;
+
+    #[test]
+    fn test_diff() {
+        let mut old = DGraph::new();
+        old.add_trace(vec![(1, 0), (2, 0)]);
+        old.add_trace(vec![(1, 0), (3, 0)]);
+        let mut new = DGraph::new();
+        new.add_trace(vec![(1, 0), (2, 0), (4, 0)]);
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added_nodes, vec![(4, 0)]);
+        assert_eq!(diff.removed_nodes, vec![(3, 0)]);
+        assert_eq!(diff.added_edges, vec![((2, 0), (4, 0))]);
+        assert_eq!(diff.removed_edges, vec![((1, 0), (3, 0))]);
+        assert_eq!(diff.weights, vec![((1, 0), -1), ((3, 0), -1), ((4, 0), 1)]);
+        assert!(old.diff(&old).is_empty());
+        assert_eq!(diff.to_string(), "+1 -1 nodes, +1 -1 edges, 3 weights changed, 1 hits gained");
+    }
+
+    #[test]
+    fn test_to_delta_catches_up() {
+        let mut old = DGraph::new();
+        old.add_trace(vec![(1, 0), (2, 0)]);
+        let mut new = DGraph::new();
+        new.add_trace(vec![(1, 0), (2, 0)]);
+        new.add_trace(vec![(1, 0), (3, 0), (2, 1)]);
+
+        let delta = old.diff(&new).to_delta();
+        old.apply_delta(&delta, 1);
+        assert!(old.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn test_successive_diffs_add_up() {
+        let mut target = DGraph::new();
+        let mut old = DGraph::new();
+        let mut new = DGraph::new();
+        new.add_trace(vec![(1, 0)]);
+        target.apply_delta(&old.diff(&new).to_delta(), 1);
+
+        old = new.clone();
+        new.add_trace(vec![(1, 0)]);
+        new.add_trace(vec![(1, 0)]);
+        target.apply_delta(&old.diff(&new).to_delta(), 1);
+        assert_eq!(target.get(&(1, 0)).unwrap().weight, 3);
+    }
+}
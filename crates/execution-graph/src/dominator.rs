@@ -0,0 +1,279 @@
+use std::fmt;
+use nodes::{GraphView, NodeId, NodeKey};
+use partition::{self, PartitionPlan, PartitionStrategy};
+
+/// Dominator tree of a graph rooted at its root: node `a` dominates `b` if
+/// every path from the root to `b` goes through `a`. Computed with the
+/// iterative algorithm of Cooper, Harvey and Kennedy, which copes with the
+/// cycles merged traces can leave.
+#[derive(Clone, Debug)]
+pub struct DominatorTree {
+    root: NodeId,
+    // immediate dominator, the root's is itself, `None` if unreachable
+    idom: Vec<Option<NodeId>>,
+    children: Vec<Vec<NodeId>>,
+    // weight of every node dominated, including itself
+    dominated: Vec<usize>,
+    // number of nodes dominated, including itself
+    sizes: Vec<usize>,
+}
+
+/// A node gating a heavy part of the graph.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Bottleneck {
+    pub key: NodeKey,
+    // nodes it dominates, including itself
+    pub nodes: usize,
+    // their total weight
+    pub weight: usize,
+}
+
+impl DominatorTree {
+    pub fn new(graph: &dyn GraphView) -> Self {
+        let arena = graph.arena();
+        let root = graph.root();
+
+        // depth-first postorder from the root
+        let mut postorder = Vec::with_capacity(arena.len());
+        let mut visited = vec![false; arena.len()];
+        let mut stack = vec![(root, 0)];
+        visited[root] = true;
+        while let Some((id, next)) = stack.pop() {
+            let children = &arena.get(id).children;
+            if next < children.len() {
+                stack.push((id, next + 1));
+                let child = children[next];
+                if !visited[child] {
+                    visited[child] = true;
+                    stack.push((child, 0));
+                }
+            } else {
+                postorder.push(id);
+            }
+        }
+        let mut number = vec![usize::MAX; arena.len()];
+        for (i, id) in postorder.iter().enumerate() {
+            number[*id] = i;
+        }
+        let mut parents: Vec<Vec<NodeId>> = vec![vec![]; arena.len()];
+        for (id, node) in arena.iter() {
+            if visited[id] {
+                for child in &node.children {
+                    parents[*child].push(id);
+                }
+            }
+        }
+
+        let mut idom: Vec<Option<NodeId>> = vec![None; arena.len()];
+        idom[root] = Some(root);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for id in postorder.iter().rev().filter(|id| **id != root) {
+                let mut new_idom: Option<NodeId> = None;
+                for parent in &parents[*id] {
+                    if idom[*parent].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => *parent,
+                        Some(current) => {
+                            let (mut a, mut b) = (current, *parent);
+                            while a != b {
+                                while number[a] < number[b] {
+                                    a = idom[a].unwrap();
+                                }
+                                while number[b] < number[a] {
+                                    b = idom[b].unwrap();
+                                }
+                            }
+                            a
+                        }
+                    });
+                }
+                if new_idom != idom[*id] {
+                    idom[*id] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut children: Vec<Vec<NodeId>> = vec![vec![]; arena.len()];
+        let mut dominated: Vec<usize> = arena.iter().map(|(_, node)| node.weight).collect();
+        let mut sizes = vec![1; arena.len()];
+        // postorder visits every node before its dominators
+        for id in &postorder {
+            if *id == root {
+                continue;
+            }
+            let parent = idom[*id].unwrap();
+            children[parent].push(*id);
+            dominated[parent] += dominated[*id];
+            sizes[parent] += sizes[*id];
+        }
+        for list in &mut children {
+            list.reverse();
+        }
+
+        DominatorTree { root, idom, children, dominated, sizes }
+    }
+
+    /// Immediate dominator of `id`; `None` for the root and unreachable nodes.
+    pub fn idom(&self, id: NodeId) -> Option<NodeId> {
+        if id == self.root {
+            return None;
+        }
+        self.idom[id]
+    }
+
+    pub fn dominates(&self, a: NodeId, b: NodeId) -> bool {
+        let mut id = b;
+        loop {
+            if id == a {
+                return true;
+            }
+            match self.idom(id) {
+                Some(parent) => id = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Nodes `id` immediately dominates.
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.children[id]
+    }
+
+    /// Total weight of the nodes `id` dominates, including itself.
+    pub fn dominated_weight(&self, id: NodeId) -> usize {
+        self.dominated[id]
+    }
+
+    /// The `n` nodes other than the root dominating the most weight.
+    pub fn bottlenecks(&self, graph: &dyn GraphView, n: usize) -> Vec<Bottleneck> {
+        let mut ids: Vec<NodeId> = (0..self.idom.len())
+            .filter(|id| *id != self.root && self.idom[*id].is_some())
+            .collect();
+        ids.sort_by_key(|id| (std::cmp::Reverse(self.dominated[*id]), *id));
+        ids.into_iter().take(n).map(|id| Bottleneck {
+            key: graph.arena().get(id).key(),
+            nodes: self.sizes[id],
+            weight: self.dominated[id],
+        }).collect()
+    }
+}
+
+impl fmt::Display for Bottleneck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}, {}) dominates {} nodes weighing {}", self.key.0, self.key.1, self.nodes, self.weight)
+    }
+}
+
+/// Only cuts edges into a dominator subtree: subtrees dominating at most a
+/// `k`-th of the total weight stay whole, heavier dominators are placed on
+/// their own and their subtrees split further. The pieces are then placed
+/// like `Multilevel` would place nodes, so every plan owns whole regions
+/// behind the checks gating them.
+pub struct DominatorCut;
+
+impl PartitionStrategy for DominatorCut {
+    fn partition(&self, graph: &dyn GraphView, k: usize) -> Vec<PartitionPlan> {
+        partition::partition_units(graph, &self.units(graph, k), k)
+    }
+
+    fn units(&self, graph: &dyn GraphView, k: usize) -> Vec<Option<usize>> {
+        let tree = DominatorTree::new(graph);
+        let target = tree.dominated_weight(graph.root()) / k.max(1);
+        let mut units: Vec<Option<usize>> = vec![None; graph.arena().len()];
+        let mut next = 0;
+        let mut stack = vec![graph.root()];
+        while let Some(id) = stack.pop() {
+            units[id] = Some(next);
+            if tree.dominated_weight(id) <= target {
+                // the whole subtree joins this unit
+                let mut subtree = tree.children(id).to_vec();
+                while let Some(child) = subtree.pop() {
+                    units[child] = Some(next);
+                    subtree.extend_from_slice(tree.children(child));
+                }
+            } else {
+                stack.extend_from_slice(tree.children(id));
+            }
+            next += 1;
+        }
+        units
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dgraph::DGraph;
+
+    // 1 branches to 2 and 3, which meet again at 4; 4 gates 5 and 6
+    fn diamond() -> DGraph {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0), (4, 0), (5, 0)]);
+        graph.add_trace(vec![(1, 0), (3, 0), (4, 0), (6, 0)]);
+        graph
+    }
+
+    fn id(graph: &DGraph, key: NodeKey) -> NodeId {
+        graph.arena().iter().find(|(_, node)| node.key() == key).unwrap().0
+    }
+
+    #[test]
+    fn test_idom() {
+        let graph = diamond();
+        let tree = graph.dominators();
+        let id = |key| id(&graph, key);
+        assert_eq!(tree.idom(graph.root()), None);
+        assert_eq!(tree.idom(id((4, 0))), Some(id((1, 0))));
+        assert_eq!(tree.idom(id((5, 0))), Some(id((4, 0))));
+        assert!(tree.dominates(id((1, 0)), id((6, 0))));
+        assert!(!tree.dominates(id((2, 0)), id((4, 0))));
+        assert_eq!(tree.dominated_weight(id((4, 0))), 4);
+    }
+
+    #[test]
+    fn test_cycle() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0), (3, 0), (2, 0), (4, 0)]);
+        let tree = graph.dominators();
+        assert_eq!(tree.idom(id(&graph, (3, 0))), Some(id(&graph, (2, 0))));
+        assert_eq!(tree.idom(id(&graph, (4, 0))), Some(id(&graph, (2, 0))));
+    }
+
+    #[test]
+    fn test_bottlenecks() {
+        let graph = diamond();
+        let bottlenecks = graph.bottlenecks(2);
+        assert_eq!(bottlenecks[0], Bottleneck { key: (1, 0), nodes: 6, weight: 8 });
+        assert_eq!(bottlenecks[1], Bottleneck { key: (4, 0), nodes: 3, weight: 4 });
+        assert_eq!(bottlenecks[1].to_string(), "(4, 0) dominates 3 nodes weighing 4");
+    }
+
+    #[test]
+    fn test_dominator_cut() {
+        let mut graph = DGraph::new();
+        for i in 0..20 {
+            graph.add_trace(vec![(1, 0), (2, 0), (10 + i, 0)]);
+            graph.add_trace(vec![(1, 0), (3, 0), (40 + i, 0)]);
+        }
+        let plans = DominatorCut.partition(&graph, 2);
+        let owner = |key| plans.iter().position(|p| p.plan.contains(&key)).unwrap();
+        // each check keeps the region behind it
+        assert!((10..30).all(|idx| owner((idx, 0)) == owner((2, 0))));
+        assert!((40..60).all(|idx| owner((idx, 0)) == owner((3, 0))));
+        assert_ne!(owner((2, 0)), owner((3, 0)));
+
+        // new nodes join the region behind their check, which stays whole
+        for i in 20..30 {
+            graph.add_trace(vec![(1, 0), (2, 0), (10 + i, 0)]);
+        }
+        let units = DominatorCut.units(&graph, 2);
+        let plans = partition::reassign_units(&graph, &plans, 2, 10, &units).plans;
+        let owner = |key| plans.iter().position(|p| p.plan.contains(&key)).unwrap();
+        assert!((10..40).all(|idx| owner((idx, 0)) == owner((2, 0))));
+    }
+}
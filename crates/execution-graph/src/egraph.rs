@@ -1,221 +1,392 @@
-
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::ops::Deref;
-use std::rc::Rc;
-use serde::ser::SerializeStruct;
+use std::io;
 use serde::{Deserialize, Serialize};
-use partition::PartitionPlan;
-use crate::nodes::{RENode, ENode};
-use crate::{db, rr};
-
+use corpus::CorpusIndex;
+use export;
+use frontier::{self, FrontierNode};
+use graph::ExecutionGraph;
+use packets::invalid_data;
+use partition::{LeafPacking, PartitionPlan, PartitionStrategy};
+use crate::nodes::{Arena, ENode, GraphView, NodeId, NodeKey};
+
+/// Bumped whenever the layout of `SerializableETree` changes.
+pub const ETREE_FORMAT_VERSION: u32 = 1;
+
+/// Prefix tree of execution paths. Every branching node keeps at least two
+/// children; the ones no trace has taken yet are unclaimed placeholders.
 pub struct ETree {
-    root: RENode,
+    arena: Arena,
+    root: NodeId,
     _counter: usize
 }
 
+#[derive(Serialize, Deserialize)]
+struct SerializedTreeNode {
+    idx: u32,
+    nth: u8,
+    weight: usize,
+    claimed: bool,
+    children: Vec<NodeId>,
+}
+
+/// On-wire form of an `ETree`; nodes are listed in arena order and refer to
+/// their children by position. `version` must stay the first field.
+#[derive(Serialize, Deserialize)]
+pub struct SerializableETree {
+    version: u32,
+    root: NodeId,
+    counter: usize,
+    nodes: Vec<SerializedTreeNode>,
+}
+
+impl Default for ETree {
+    // rooted like `DGraph::new`
+    fn default() -> Self {
+        Self::new(ENode::new_claimed(0, 0))
+    }
+}
+
 impl ETree {
-    pub fn new(root: RENode) -> Self {
+    pub fn new(root: ENode) -> Self {
+        let mut arena = Arena::new();
+        let root = arena.alloc(root);
         ETree {
+            arena,
             root,
             _counter: u32::MAX as usize,
         }
     }
+
+    pub fn node(&self, id: NodeId) -> &ENode {
+        self.arena.get(id)
+    }
+
+    // the child of `parent` taking `key`, claiming a placeholder or creating
+    // it if no trace went there yet; `true` when it did not exist before
+    fn child_for(&mut self, parent: NodeId, key: NodeKey) -> (NodeId, bool) {
+        let found = self.arena.get(parent).children.iter()
+            .copied()
+            .find(|child| {
+                let child_ref = self.arena.get(*child);
+                child_ref._claimed && child_ref.key() == key
+            });
+        if let Some(child) = found {
+            return (child, false);
+        }
+
+        let unclaimed = self.arena.get(parent).children.iter()
+            .copied()
+            .find(|child| !self.arena.get(*child)._claimed);
+        let child = match unclaimed {
+            Some(child) => {
+                let child_ref = self.arena.get_mut(child);
+                child_ref._claimed = true;
+                child_ref.idx = key.0;
+                child_ref.nth = key.1;
+                child
+            }
+            None => {
+                let new_node = self.arena.alloc(ENode::new_claimed(key.0, key.1));
+                self.arena.get_mut(parent).children.push(new_node);
+                new_node
+            }
+        };
+        while self.arena.get(parent).children.len() < 2 {
+            let placeholder = self.arena.alloc(ENode::new_unclaimed(
+                self._counter as u32
+            ));
+            self.arena.get_mut(parent).children.push(placeholder);
+            self._counter -= 1;
+        }
+        (child, true)
+    }
 }
 
 impl ETree {
-    pub fn add_trace(&mut self, mut trace: Vec<(u32, u8)>) {
-        let mut current_node = self.root.clone();
-        let (start_idx, start_nth) = trace.remove(0);
-        assert_eq!(db!(current_node).idx, start_idx);
-        assert_eq!(db!(current_node).nth, start_nth);
-        for (idx, nth) in trace {
-            let mut found = None;
-            for child in &db!(current_node).children {
-                if idx == db!(child).idx && nth == db!(child).nth {
-                    found = Some(child.clone());
-                    break;
-                }
+    /// Adds the path of `trace`, see `ExecutionGraph::add_trace`.
+    pub fn add_trace(&mut self, trace: Vec<NodeKey>) {
+        let mut current_node = self.root;
+        for key in trace {
+            let (child, created) = self.child_for(current_node, key);
+            if !created {
+                self.arena.get_mut(child).weight += 1;
             }
-            match found {
-                None => {
-                    let (is_current_unclaimed, next_node) = {
-                        let mut is_current_unclaimed = false;
-                        let mut next_node = None;
-                        for child in &db!(current_node).children {
-                            if !(db!(child)._claimed) {
-                                is_current_unclaimed = true;
-                            }
-                            if is_current_unclaimed {
-                                db!(mut, child)._claimed = true;
-                                db!(mut, child).nth = nth;
-                                db!(mut, child).idx = idx;
-                                next_node = Some(child.clone());
-                                break;
-                            }
-                        }
-
-                        if !is_current_unclaimed {
-                            let new_node = rr!(ENode::new_claimed(idx, nth));
-                            db!(mut, current_node).children.push(new_node.clone());
-                            next_node = Some(new_node);
-                        }
-                        (is_current_unclaimed, next_node.expect("next node should be found"))
-                    };
-                    while db!(current_node).children.len() < 2 {
-                        db!(mut, current_node).children.push(
-                            rr!(ENode::new_unclaimed(
-                                self._counter as u32
-                            )),
-                        );
-                        self._counter -= 1;
-                    }
-                    current_node = next_node;
+            current_node = child;
+        }
+    }
 
+    /// Adds the paths and weights of `other`; its unclaimed placeholders
+    /// carry no information and are skipped.
+    pub fn merge(&mut self, other: &ETree) {
+        assert_eq!(self.arena.get(self.root).key(), other.arena.get(other.root).key(),
+                   "cannot merge trees with different roots");
+        self.arena.get_mut(self.root).weight += other.arena.get(other.root).weight;
+
+        let mut stack = vec![(self.root, other.root)];
+        while let Some((self_id, other_id)) = stack.pop() {
+            for other_child in &other.arena.get(other_id).children {
+                let other_child_ref = other.arena.get(*other_child);
+                if !other_child_ref._claimed {
+                    continue;
                 }
-                Some(child) => {
-                    current_node = child;
+                let (child, created) = self.child_for(self_id, other_child_ref.key());
+                if created {
+                    self.arena.get_mut(child).weight = other_child_ref.weight;
+                } else {
+                    self.arena.get_mut(child).weight += other_child_ref.weight;
                 }
+                stack.push((child, *other_child));
             }
         }
     }
 
-
-    pub fn _dfs_weight_helper(node: &RENode, parent_weight: usize) {
-        let cumulated = {
-            let node_ref = db!(node);
-            let mut cumulated = parent_weight + node_ref.weight as usize;
-            if node_ref.children.len() > 0 {
-                node_ref.children.iter().for_each(
-                    |child: &RENode| Self::_dfs_weight_helper(child, cumulated)
-                )
-            }
-            cumulated
+    pub fn serialize(&self) -> Vec<u8> {
+        let nodes = self.arena.iter().map(|(_, node)| SerializedTreeNode {
+            idx: node.idx,
+            nth: node.nth,
+            weight: node.weight,
+            claimed: node._claimed,
+            children: node.children.clone(),
+        }).collect();
+
+        let set = SerializableETree {
+            version: ETREE_FORMAT_VERSION,
+            root: self.root,
+            counter: self._counter,
+            nodes,
         };
-        db!(mut, node)._cumulated = cumulated;
+
+        bincode::serialize(&set).unwrap()
     }
 
-    pub fn _update_weight(&mut self) {
-        Self::_dfs_weight_helper(&self.root, 0);
+    /// Nodes with unclaimed placeholder children, i.e. branches not taken yet.
+    pub fn frontier(&self, corpus: &CorpusIndex) -> Vec<FrontierNode> {
+        frontier::frontier(self, corpus)
     }
 
-    pub fn _dfs_grab_node_helper(node: &RENode, parts: &mut Vec<PartitionPlan>, trace: Vec<RENode>) {
-        let node_ref = db!(node);
+    /// Rebuilds a tree from `export::to_json` output.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let graph = export::parse_json(json)?;
+        let positions = graph.positions();
+        let mut arena = Arena::new();
+        for node in &graph.nodes {
+            arena.alloc(ENode {
+                idx: node.idx,
+                nth: node.nth,
+                weight: node.weight,
+                children: node.children.iter().map(|child| positions[child]).collect(),
+                _cumulated: 0,
+                _claimed: node.claimed,
+            });
+        }
+        // placeholders count down from u32::MAX, continue below the lowest
+        let counter = graph.nodes.iter()
+            .filter(|node| !node.claimed && node.id != graph.root)
+            .map(|node| node.idx as usize - 1)
+            .min()
+            .unwrap_or(u32::MAX as usize);
+        Ok(ETree {
+            arena,
+            root: positions[&graph.root],
+            _counter: counter,
+        })
+    }
 
-        // is leaf
-        if node_ref.children.len() == 0 {
-            parts.push(PartitionPlan {
-                plan: vec![trace, vec![node.clone()]].concat(),
-                weight: node_ref._cumulated as usize,
-                dependencies: HashMap::new(),
+    pub fn deserialize(bytes: Vec<u8>) -> Self {
+        Self::try_deserialize(bytes).expect("cannot deserialize ETree")
+    }
+
+    /// `deserialize` for bytes received from a peer.
+    pub fn try_deserialize(bytes: Vec<u8>) -> io::Result<Self> {
+        let version: u32 = bincode::deserialize(&bytes).map_err(invalid_data)?;
+        if version != ETREE_FORMAT_VERSION {
+            return Err(invalid_data(format!("unsupported ETree format version {}", version)));
+        }
+
+        let set: SerializableETree = bincode::deserialize(&bytes).map_err(invalid_data)?;
+        let len = set.nodes.len();
+        if set.root >= len || set.nodes.iter().any(|node| node.children.iter().any(|child| *child >= len)) {
+            return Err(invalid_data("ETree node out of range"));
+        }
+        let mut arena = Arena::new();
+        for node in set.nodes {
+            arena.alloc(ENode {
+                idx: node.idx,
+                nth: node.nth,
+                weight: node.weight,
+                children: node.children,
+                _cumulated: 0,
+                _claimed: node.claimed,
             });
         }
-        // is not leaf
-        else {
-            let mut new_trace = trace.clone();
-            new_trace.push(node.clone());
-            for child in &node_ref.children {
-                Self::_dfs_grab_node_helper(child, parts, (new_trace.clone()));
-            }
+        Ok(ETree {
+            arena,
+            root: set.root,
+            _counter: set.counter,
+        })
+    }
+
+    pub fn _dfs_weight_helper(arena: &mut Arena, node: NodeId, parent_weight: usize) {
+        let cumulated = parent_weight + arena.get(node).weight;
+        for child in arena.get(node).children.clone() {
+            Self::_dfs_weight_helper(arena, child, cumulated);
         }
+        arena.get_mut(node)._cumulated = cumulated;
+    }
+
+    pub fn _update_weight(&mut self) {
+        Self::_dfs_weight_helper(&mut self.arena, self.root, 0);
     }
 
     pub fn partition(&mut self, k: usize) -> Vec<PartitionPlan> {
-        // stage 1: update cumulated weight
-        self._update_weight();
+        // `LeafPacking` sums path weights itself, without the recursive
+        // `_update_weight` that a deep tree would overflow the stack in
+        LeafPacking.partition(self, k)
+    }
 
-        // stage 2: find all leaves
-        let mut leaves = vec![];
-        Self::_dfs_grab_node_helper(&self.root, &mut leaves, vec![]);
+}
 
-        let mut total_weight = 0;
+impl GraphView for ETree {
+    fn root(&self) -> NodeId {
+        self.root
+    }
 
-        for leave in &leaves {
-            total_weight += db!(leave.plan.last().unwrap())._cumulated;
-        }
+    fn arena(&self) -> &Arena {
+        &self.arena
+    }
+}
 
-        let approx_partition_weight = total_weight / k;
+impl ExecutionGraph for ETree {
+    fn add_trace(&mut self, trace: Vec<NodeKey>) {
+        ETree::add_trace(self, trace)
+    }
 
-        #[cfg(test)] {
-            println!("total weight: {}", total_weight);
-            println!("approx_partition_weight: {}", approx_partition_weight);
-            leaves.iter().map(|leaf| {
-                println!("leaf: {:?} with weight {:?}", leaf.plan.iter().map(|node| {
-                    db!(node).idx
-                }).collect::<Vec<_>>(), leaf.weight);
-            }).collect::<Vec<_>>();
-        }
+    fn merge(&mut self, other: &ETree) {
+        ETree::merge(self, other)
+    }
 
-        // stage3: merge until we have k partitions
-        let mut partitions: Vec<PartitionPlan> = vec![];
-
-        // we 'll use a greedy algorithm to merge leaves
-
-        // first, sort leaves by weight
-        leaves.sort_by(|a, b| b.weight.cmp(&a.weight));
-        // then, merge leaves until we have k partitions
-        for leaf in leaves {
-            let mut merged = false;
-            for partition in &mut partitions {
-                if partition.weight + leaf.weight <= approx_partition_weight {
-                    partition.merge(&leaf);
-                    merged = true;
-                    break;
-                }
-            }
-            if !merged {
-                partitions.push(leaf);
-            }
-        }
-        // if we still have more than k partitions, we merge from last to first
-        while partitions.len() > k {
-            let last_p = partitions.pop().expect("no partition to pop");
-            partitions.last_mut().unwrap().merge(&last_p);
-        }
-        partitions
+    fn serialize(&self) -> Vec<u8> {
+        ETree::serialize(self)
+    }
+
+    fn deserialize(bytes: Vec<u8>) -> Self {
+        ETree::deserialize(bytes)
     }
 
+    fn partition(&mut self, k: usize) -> Vec<PartitionPlan> {
+        ETree::partition(self, k)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dgraph::DGraph;
+
+    fn is_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_send_sync() {
+        is_send_sync::<ETree>();
+    }
 
     #[test]
     fn test_insert() {
         // 1 -> 2
         //   -> 3 -> 4
         //        -> 5
+        let mut tree = ETree::new(ENode::new_unclaimed(1));
+        tree.add_trace(vec![(2, 0)]);
+        tree.add_trace(vec![(3, 0), (4, 0)]);
+        tree.add_trace(vec![(3, 0), (5, 0)]);
+
+        let root = tree.node(tree.root());
+        assert_eq!(root.children.len(), 2);
+        let three = tree.node(root.children[1]);
+        assert_eq!(three.key(), (3, 0));
+        assert_eq!(three.children.iter().map(|c| tree.node(*c).key()).collect::<Vec<_>>(),
+                   vec![(4, 0), (5, 0)]);
+    }
 
-        macro_rules! create_enode {
-            ($idx: expr) => {
-                ENode {
-                    idx: $idx,
-                    nth: 0,
-                    weight: 1,
-                    _cumulated: 0,
-                    _claimed: false,
-                    children: vec![],
-                }
-            };
+    #[test]
+    fn test_revisit_counts_hits() {
+        let mut tree = ETree::new(ENode::new_unclaimed(1));
+        tree.add_trace(vec![(2, 0)]);
+        tree.add_trace(vec![(2, 0)]);
+        let root = tree.node(tree.root());
+        assert_eq!(tree.node(root.children[0]).weight, 2);
+    }
+
+    #[test]
+    fn test_trace_contract() {
+        // the same traces build the same nodes in both models
+        let traces = vec![vec![], vec![(2, 0), (3, 0)], vec![(2, 0), (4, 0)]];
+        let mut tree = ETree::default();
+        let mut graph = DGraph::new();
+        for trace in traces {
+            ExecutionGraph::add_trace(&mut tree, trace.clone());
+            ExecutionGraph::add_trace(&mut graph, trace);
         }
+        let claimed: Vec<NodeKey> = tree.arena().iter()
+            .filter(|(_, node)| node._claimed)
+            .map(|(_, node)| node.key())
+            .collect();
+        assert_eq!(claimed, vec![(0, 0), (2, 0), (3, 0), (4, 0)]);
+        assert!(claimed.iter().all(|key| graph.get(key).is_some()));
+        assert_eq!(graph.len(), claimed.len());
+    }
 
-        let node1 = rr!(create_enode!(1));
-        let mut tree = ETree::new(node1);
-        tree.add_trace(vec![(1, 0), (2, 0)]);
-        tree.add_trace(vec![(1, 0), (3, 0), (4, 0)]);
-        tree.add_trace(vec![(1, 0), (3, 0), (5, 0)]);
-
-        // dfs print
-        fn dfs_print(node: &RENode, depth: usize) {
-            let node_ref = db!(node);
-            println!("{}{}: {}", " ".repeat(depth * 2), node_ref.idx, node_ref._cumulated);
-            for child in &node_ref.children {
-                dfs_print(child, depth + 1);
-            }
+    #[test]
+    fn test_merge() {
+        let mut a = ETree::new(ENode::new_unclaimed(1));
+        a.add_trace(vec![(2, 0)]);
+        let mut b = ETree::new(ENode::new_unclaimed(1));
+        b.add_trace(vec![(2, 0)]);
+        b.add_trace(vec![(3, 0), (4, 0)]);
+
+        a.merge(&b);
+        let root = a.node(a.root());
+        let keys: Vec<NodeKey> = root.children.iter().map(|c| a.node(*c).key()).collect();
+        assert_eq!(keys, vec![(2, 0), (3, 0)]);
+        assert_eq!(a.node(root.children[0]).weight, 2);
+        let three = a.node(root.children[1]);
+        assert_eq!(a.node(three.children[0]).key(), (4, 0));
+        // placeholder padding is kept
+        assert_eq!(three.children.len(), 2);
+        assert!(!a.node(three.children[1])._claimed);
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let mut tree = ETree::new(ENode::new_unclaimed(1));
+        tree.add_trace(vec![(2, 0)]);
+        tree.add_trace(vec![(3, 0), (4, 0)]);
+
+        let decoded = ETree::deserialize(tree.serialize());
+        assert_eq!(decoded.arena().len(), tree.arena().len());
+        for (id, node) in tree.arena().iter() {
+            let other = decoded.node(id);
+            assert_eq!(other.key(), node.key());
+            assert_eq!(other.weight, node.weight);
+            assert_eq!(other._claimed, node._claimed);
+            assert_eq!(other.children, node.children);
         }
+        assert_eq!(decoded._counter, tree._counter);
 
-        dfs_print(&tree.root, 0);
+        let mut bytes = tree.serialize();
+        bytes.truncate(bytes.len() - 1);
+        assert!(ETree::try_deserialize(bytes).is_err());
+        assert!(ETree::try_deserialize(vec![9, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut tree = ETree::new(ENode::new_unclaimed(1));
+        tree.add_trace(vec![(2, 0)]);
+        tree.add_trace(vec![(3, 0), (4, 0)]);
+
+        let decoded = ETree::from_json(&export::to_json(&tree, &[])).unwrap();
+        assert_eq!(export::to_json_graph(&decoded, &[]), export::to_json_graph(&tree, &[]));
+        assert_eq!(decoded._counter, tree._counter);
     }
 
     #[test]
@@ -223,36 +394,18 @@ mod tests {
         // 1 -> 2
         //   -> 3 -> 4
         //        -> 5
-
-        macro_rules! create_enode {
-            ($idx: expr) => {
-                ENode {
-                    idx: $idx,
-                    nth: 0,
-                    weight: 1,
-                    _cumulated: 0,
-                    _claimed: false,
-                    children: vec![],
-                }
-            };
-        }
-
-        let node1 = rr!(create_enode!(1));
-        let node2 = rr!(create_enode!(2));
-        let node3 = rr!(create_enode!(3));
-        let node4 = rr!(create_enode!(4));
-        let node5 = rr!(create_enode!(5));
-        db!(mut, node1).children.push(node2.clone());
-        db!(mut, node1).children.push(node3.clone());
-        db!(mut, node3).children.push(node4.clone());
-        db!(mut, node3).children.push(node5.clone());
-
-
-        let mut tree = ETree::new(node1);
-
-        for i in tree.partition(2) {
-            println!("partition: {:?} with weight {:?}",
-                     i.plan.iter().map(|x| db!(x).idx).collect::<Vec<u32>>(), i.weight);
-        }
+        let mut tree = ETree::new(ENode::new_unclaimed(1));
+        let node2 = tree.arena.alloc(ENode::new_unclaimed(2));
+        let node3 = tree.arena.alloc(ENode::new_unclaimed(3));
+        let node4 = tree.arena.alloc(ENode::new_unclaimed(4));
+        let node5 = tree.arena.alloc(ENode::new_unclaimed(5));
+        let root = tree.root();
+        tree.arena.get_mut(root).children.push(node2);
+        tree.arena.get_mut(root).children.push(node3);
+        tree.arena.get_mut(node3).children.push(node4);
+        tree.arena.get_mut(node3).children.push(node5);
+
+        let partitions = tree.partition(2);
+        assert_eq!(partitions.len(), 2);
     }
 }
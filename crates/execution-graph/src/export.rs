@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use serde::{Deserialize, Serialize};
+use nodes::{GraphView, NodeId};
+use partition::{owners, PartitionPlan};
+
+// fill colors for partitions, reused cyclically
+const PALETTE: [&str; 10] = [
+    "lightblue", "lightcoral", "palegreen", "khaki", "plum",
+    "lightsalmon", "paleturquoise", "wheat", "lightpink", "lightgray",
+];
+
+/// Node of the JSON export, refers to its children by `id`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JsonNode {
+    pub id: NodeId,
+    pub idx: u32,
+    pub nth: u8,
+    pub weight: usize,
+    pub claimed: bool,
+    pub children: Vec<NodeId>,
+    // index of the owning plan, if plans were given
+    #[serde(default)]
+    pub partition: Option<usize>,
+    // hits per replica, see `GraphView::counters`
+    #[serde(default)]
+    pub counters: Vec<(u32, usize)>,
+}
+
+/// JSON form of a `DGraph` or `ETree`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JsonGraph {
+    pub root: NodeId,
+    pub nodes: Vec<JsonNode>,
+}
+
+/// Graphviz rendering; nodes are labeled `idx/nth` with their weight and
+/// filled with the color of their owning plan. Unclaimed nodes are dashed.
+pub fn to_dot(graph: &dyn GraphView, plans: &[PartitionPlan]) -> String {
+    let owner = owners(plans);
+    let arena = graph.arena();
+    let mut dot = String::from("digraph execution {\n");
+    for (id, node) in arena.iter() {
+        let fill = owner.get(&node.key())
+            .map(|p| PALETTE[p % PALETTE.len()])
+            .unwrap_or("white");
+        let style = if node._claimed { "filled" } else { "filled,dashed" };
+        let shape = if id == graph.root() { "doublecircle" } else { "ellipse" };
+        writeln!(dot, "  n{} [label=\"{}/{}\\nw={}\", shape={}, style=\"{}\", fillcolor={}];",
+                 id, node.idx, node.nth, node.weight, shape, style, fill).unwrap();
+    }
+    for (id, node) in arena.iter() {
+        for child in &node.children {
+            writeln!(dot, "  n{} -> n{};", id, child).unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn to_json_graph(graph: &dyn GraphView, plans: &[PartitionPlan]) -> JsonGraph {
+    let owner = owners(plans);
+    let nodes = graph.arena().iter().map(|(id, node)| JsonNode {
+        id,
+        idx: node.idx,
+        nth: node.nth,
+        weight: node.weight,
+        claimed: node._claimed,
+        children: node.children.clone(),
+        partition: owner.get(&node.key()).copied(),
+        counters: graph.counters(id).to_vec(),
+    }).collect();
+    JsonGraph {
+        root: graph.root(),
+        nodes,
+    }
+}
+
+pub fn to_json(graph: &dyn GraphView, plans: &[PartitionPlan]) -> String {
+    serde_json::to_string_pretty(&to_json_graph(graph, plans)).unwrap()
+}
+
+pub fn parse_json(json: &str) -> Result<JsonGraph, serde_json::Error> {
+    let graph: JsonGraph = serde_json::from_str(json)?;
+    let position = graph.positions();
+    if !position.contains_key(&graph.root) {
+        return Err(serde::de::Error::custom("root is not a node"));
+    }
+    for node in &graph.nodes {
+        if node.children.iter().any(|child| !position.contains_key(child)) {
+            return Err(serde::de::Error::custom(format!("node {} has an unknown child", node.id)));
+        }
+    }
+    Ok(graph)
+}
+
+impl JsonGraph {
+    /// Position in `nodes` of every node id.
+    pub fn positions(&self) -> HashMap<NodeId, usize> {
+        self.nodes.iter().enumerate().map(|(position, node)| (node.id, position)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::sample;
+    use partition::{CountChunking, PartitionStrategy};
+
+    #[test]
+    fn test_dot() {
+        let graph = sample();
+        let plans = CountChunking.partition(&graph, 2);
+        let dot = to_dot(&graph, &plans);
+        assert!(dot.starts_with("digraph execution {"));
+        assert!(dot.contains("label=\"2/0\\nw=1\""));
+        assert!(dot.contains("label=\"1/0\\nw=3\""));
+        assert!(dot.contains("fillcolor=lightblue"));
+        assert!(dot.contains("fillcolor=lightcoral"));
+        assert!(dot.contains("n1 -> n2;"));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let graph = sample();
+        let plans = CountChunking.partition(&graph, 2);
+        let parsed = parse_json(&to_json(&graph, &plans)).unwrap();
+        assert_eq!(parsed, to_json_graph(&graph, &plans));
+        assert_eq!(parsed.nodes[1].partition, Some(0));
+        assert_eq!(parsed.nodes[3].partition, Some(1));
+    }
+
+    #[test]
+    fn test_json_rejects_dangling_child() {
+        let json = r#"{"root": 0, "nodes": [
+            {"id": 0, "idx": 0, "nth": 0, "weight": 1, "claimed": true, "children": [7]}
+        ]}"#;
+        assert!(parse_json(json).is_err());
+    }
+}
@@ -0,0 +1,14 @@
+//! Graphs shared by the tests of several modules.
+
+use dgraph::DGraph;
+
+/// 1 -> 2
+///   -> 3 -> 4
+///        -> 5
+pub fn sample() -> DGraph {
+    let mut graph = DGraph::new();
+    graph.add_trace(vec![(1, 0), (2, 0)]);
+    graph.add_trace(vec![(1, 0), (3, 0), (4, 0)]);
+    graph.add_trace(vec![(1, 0), (3, 0), (5, 0)]);
+    graph
+}
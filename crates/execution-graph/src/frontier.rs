@@ -0,0 +1,109 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap};
+use corpus::{CorpusId, CorpusIndex};
+use nodes::{GraphView, NodeKey};
+
+/// A node with successors nobody has taken yet.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FrontierNode {
+    pub key: NodeKey,
+    pub weight: usize,
+    // unclaimed placeholder children (`ETree`)
+    pub placeholders: usize,
+    // edges that follow this node's edge elsewhere in the graph, but were
+    // never taken from this node
+    pub untaken: Vec<u32>,
+    // corpus inputs reaching this node, the parent of its unexplored successors
+    pub inputs: Vec<CorpusId>,
+}
+
+/// Claimed nodes with unexplored successors: `ETree` placeholders, or
+/// successor edges seen after another `nth` variant (or, in an `ETree`,
+/// another occurrence) of the same edge but not after this node. Ordered
+/// by most unexplored successors, then least hit, so seeds at the edge of
+/// explored territory come first.
+pub fn frontier(graph: &dyn GraphView, corpus: &CorpusIndex) -> Vec<FrontierNode> {
+    let arena = graph.arena();
+    let successors = |id| -> BTreeSet<u32> {
+        arena.get(id).children.iter()
+            .map(|child| arena.get(*child))
+            .filter(|child| child._claimed)
+            .map(|child| child.idx)
+            .collect()
+    };
+
+    // successor edges of every edge, over all its nodes
+    let mut seen: HashMap<u32, BTreeSet<u32>> = HashMap::new();
+    for (id, node) in arena.iter() {
+        if node._claimed {
+            seen.entry(node.idx).or_default().extend(successors(id));
+        }
+    }
+
+    let mut result = vec![];
+    for (id, node) in arena.iter() {
+        if !node._claimed {
+            continue;
+        }
+        let placeholders = node.children.iter().filter(|child| !arena.get(**child)._claimed).count();
+        let untaken: Vec<u32> = seen[&node.idx].difference(&successors(id)).copied().collect();
+        if placeholders == 0 && untaken.is_empty() {
+            continue;
+        }
+        result.push(FrontierNode {
+            key: node.key(),
+            weight: node.weight,
+            placeholders,
+            untaken,
+            inputs: corpus.inputs(&node.key()).to_vec(),
+        });
+    }
+    result.sort_by_key(|f| (Reverse(f.placeholders + f.untaken.len()), f.weight, f.key));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dgraph::DGraph;
+    use egraph::ETree;
+    use nodes::ENode;
+
+    #[test]
+    fn test_dgraph_frontier() {
+        let traces = vec![
+            vec![(1, 0), (2, 0), (5, 0)],
+            vec![(1, 0), (2, 0), (6, 0)],
+            vec![(1, 0), (3, 0), (2, 1), (5, 1)],
+        ];
+        let mut graph = DGraph::new();
+        let mut corpus = CorpusIndex::new();
+        for (input, trace) in traces.into_iter().enumerate() {
+            corpus.record(input, &trace);
+            graph.add_trace(trace);
+        }
+
+        let frontier = graph.frontier(&corpus);
+        // (2, 1) went on to 5 but never to 6; (1, 0) went everywhere it could
+        assert_eq!(frontier.len(), 1);
+        assert_eq!(frontier[0].key, (2, 1));
+        assert_eq!(frontier[0].untaken, vec![6]);
+        assert_eq!(frontier[0].inputs, vec![2]);
+    }
+
+    #[test]
+    fn test_etree_frontier() {
+        let mut tree = ETree::new(ENode::new_unclaimed(1));
+        let mut corpus = CorpusIndex::new();
+        let trace = vec![(2, 0), (3, 0)];
+        corpus.record(0, &trace);
+        tree.add_trace(trace);
+
+        let frontier = tree.frontier(&corpus);
+        // every claimed node but the leaf still has a placeholder
+        let keys: Vec<NodeKey> = frontier.iter().map(|f| f.key).collect();
+        assert!(keys.contains(&(2, 0)));
+        assert!(frontier.iter().all(|f| f.placeholders > 0));
+        assert!(frontier.iter().filter(|f| f.key == (2, 0)).all(|f| f.inputs == vec![0]));
+    }
+}
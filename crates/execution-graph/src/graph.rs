@@ -0,0 +1,15 @@
+use nodes::{GraphView, NodeKey};
+use partition::PartitionPlan;
+
+/// Operations shared by the execution models, so a campaign can pick the
+/// edge graph (`DGraph`) or the path tree (`ETree`).
+pub trait ExecutionGraph: GraphView {
+    /// Adds one execution. A trace lists the keys the execution went
+    /// through after the root, in order; the root is left out since every
+    /// execution starts there. An empty trace adds nothing.
+    fn add_trace(&mut self, trace: Vec<NodeKey>);
+    fn merge(&mut self, other: &Self) where Self: Sized;
+    fn serialize(&self) -> Vec<u8>;
+    fn deserialize(bytes: Vec<u8>) -> Self where Self: Sized;
+    fn partition(&mut self, k: usize) -> Vec<PartitionPlan>;
+}
@@ -1,7 +1,32 @@
-mod egraph;
-// mod dgraph;
+pub mod egraph;
+pub mod graph;
 pub mod nodes;
 pub mod dgraph;
+pub mod delta;
+pub mod packets;
 pub mod partition;
+pub mod multilevel;
+pub mod assignment;
+pub mod export;
+pub mod stats;
+pub mod snapshot;
+pub mod compress;
+pub mod context;
+pub mod codemap;
+pub mod rarity;
+pub mod corpus;
+pub mod frontier;
+pub mod dominator;
+pub mod path;
+pub mod diff;
+
+#[cfg(test)]
+mod fixtures;
 
 extern crate serde;
+extern crate bincode;
+extern crate serde_json;
+
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
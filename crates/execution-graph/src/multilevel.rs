@@ -0,0 +1,571 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+// allowed part weight above the average, in percent
+const IMBALANCE_PCT: usize = 3;
+// stop coarsening once the graph is this many times larger than k
+const COARSEN_FACTOR: usize = 16;
+// an FM pass gives up after this many moves without improving the cut
+const FM_MAX_FRUITLESS_MOVES: usize = 64;
+const FM_PASSES: usize = 8;
+
+/// Undirected graph with vertex and edge weights, the input of the
+/// multilevel partitioner. Vertices are `0..len()`.
+#[derive(Clone, Debug, Default)]
+pub struct WeightedGraph {
+    pub vertex_weights: Vec<usize>,
+    // `(neighbor, edge weight)`, stored on both endpoints
+    pub adjacency: Vec<Vec<(usize, usize)>>,
+}
+
+impl WeightedGraph {
+    pub fn new() -> Self {
+        WeightedGraph::default()
+    }
+
+    pub fn add_vertex(&mut self, weight: usize) -> usize {
+        self.vertex_weights.push(weight);
+        self.adjacency.push(vec![]);
+        self.vertex_weights.len() - 1
+    }
+
+    /// Adds `weight` to the edge `a - b`, creating it if needed.
+    /// Self loops do not affect any cut and are ignored.
+    pub fn add_edge(&mut self, a: usize, b: usize, weight: usize) {
+        if a == b {
+            return;
+        }
+        Self::bump(&mut self.adjacency[a], b, weight);
+        Self::bump(&mut self.adjacency[b], a, weight);
+    }
+
+    fn bump(neighbors: &mut Vec<(usize, usize)>, to: usize, weight: usize) {
+        match neighbors.iter_mut().find(|(n, _)| *n == to) {
+            Some((_, w)) => *w += weight,
+            None => neighbors.push((to, weight)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.vertex_weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertex_weights.is_empty()
+    }
+
+    pub fn total_weight(&self) -> usize {
+        self.vertex_weights.iter().sum()
+    }
+
+    /// Total weight of edges whose endpoints are in different parts.
+    pub fn cut(&self, parts: &[usize]) -> usize {
+        let mut cut = 0;
+        for (v, neighbors) in self.adjacency.iter().enumerate() {
+            for (u, w) in neighbors {
+                if v < *u && parts[v] != parts[*u] {
+                    cut += w;
+                }
+            }
+        }
+        cut
+    }
+
+    /// The subgraph induced by `vertices`; vertex `i` of the result is
+    /// `vertices[i]`.
+    pub fn subgraph(&self, vertices: &[usize]) -> WeightedGraph {
+        let mut index = HashMap::new();
+        let mut sub = WeightedGraph::new();
+        for v in vertices {
+            index.insert(*v, sub.add_vertex(self.vertex_weights[*v]));
+        }
+        for v in vertices {
+            for (u, w) in &self.adjacency[*v] {
+                if let Some(u) = index.get(u) {
+                    if index[v] < *u {
+                        sub.add_edge(index[v], *u, *w);
+                    }
+                }
+            }
+        }
+        sub
+    }
+
+    /// Sum of vertex weights per part.
+    pub fn part_weights(&self, parts: &[usize], k: usize) -> Vec<usize> {
+        let mut weights = vec![0; k];
+        for (v, part) in parts.iter().enumerate() {
+            weights[*part] += self.vertex_weights[v];
+        }
+        weights
+    }
+}
+
+/// Balanced k-way partitioning: heavy-edge coarsening, greedy graph growing
+/// on the coarsest graph, then Fiduccia–Mattheyses refinement on every level
+/// while projecting back. Returns the part of every vertex. Deterministic for
+/// a given graph.
+pub fn partition(graph: &WeightedGraph, k: usize) -> Vec<usize> {
+    partition_shares(graph, &vec![1; k])
+}
+
+/// Like `partition`, but part `p` gets `shares[p]` shares of the total
+/// weight instead of an equal one.
+pub fn partition_shares(graph: &WeightedGraph, shares: &[usize]) -> Vec<usize> {
+    let k = shares.len();
+    assert!(k > 0, "cannot partition into zero parts");
+    assert!(shares.iter().all(|s| *s > 0), "every part needs a share");
+    if k == 1 || graph.len() <= 1 {
+        return vec![0; graph.len()];
+    }
+    let targets = target_weights(graph, shares);
+    let limits = max_part_weights(graph, shares);
+
+    // stage 1: coarsen
+    let mut levels: Vec<(WeightedGraph, Vec<usize>)> = vec![];
+    let mut current = graph.clone();
+    let max_vertex_weight = (targets.iter().copied().min().unwrap() / 2).max(1);
+    while current.len() > COARSEN_FACTOR * k {
+        let (coarse, map) = coarsen(&current, max_vertex_weight);
+        // matching stalled, coarsening further is pointless
+        if coarse.len() * 20 > current.len() * 19 {
+            break;
+        }
+        levels.push((current, map));
+        current = coarse;
+    }
+
+    // stage 2: initial partition of the coarsest graph
+    let mut parts = grow_regions(&current, &targets);
+    refine(&current, &mut parts, &limits);
+
+    // stage 3: project back and refine on each level
+    while let Some((finer, map)) = levels.pop() {
+        parts = map.iter().map(|coarse| parts[*coarse]).collect();
+        current = finer;
+        refine(&current, &mut parts, &limits);
+    }
+    parts
+}
+
+// heavy-edge matching; returns the coarse graph and the fine -> coarse map
+fn coarsen(graph: &WeightedGraph, max_vertex_weight: usize) -> (WeightedGraph, Vec<usize>) {
+    let n = graph.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by_key(|v| (graph.adjacency[*v].len(), *v));
+
+    let mut matched = vec![usize::MAX; n];
+    for v in order {
+        if matched[v] != usize::MAX {
+            continue;
+        }
+        let mut best: Option<(usize, usize)> = None;
+        for (u, w) in &graph.adjacency[v] {
+            if matched[*u] != usize::MAX
+                || graph.vertex_weights[v] + graph.vertex_weights[*u] > max_vertex_weight {
+                continue;
+            }
+            let better = match best {
+                None => true,
+                Some((b, bw)) => (*w, Reverse(graph.vertex_weights[*u]), Reverse(*u))
+                    > (bw, Reverse(graph.vertex_weights[b]), Reverse(b)),
+            };
+            if better {
+                best = Some((*u, *w));
+            }
+        }
+        match best {
+            Some((u, _)) => {
+                matched[v] = u;
+                matched[u] = v;
+            }
+            None => matched[v] = v,
+        }
+    }
+
+    let mut map = vec![usize::MAX; n];
+    let mut coarse = WeightedGraph::new();
+    for v in 0..n {
+        if map[v] != usize::MAX {
+            continue;
+        }
+        let u = matched[v];
+        let weight = if u == v {
+            graph.vertex_weights[v]
+        } else {
+            graph.vertex_weights[v] + graph.vertex_weights[u]
+        };
+        let c = coarse.add_vertex(weight);
+        map[v] = c;
+        map[u] = c;
+    }
+    for v in 0..n {
+        for (u, w) in &graph.adjacency[v] {
+            if v < *u {
+                coarse.add_edge(map[v], map[*u], *w);
+            }
+        }
+    }
+    (coarse, map)
+}
+
+// each part's share of the total weight
+fn target_weights(graph: &WeightedGraph, shares: &[usize]) -> Vec<usize> {
+    let total_shares: usize = shares.iter().sum();
+    shares.iter().map(|s| graph.total_weight() * s / total_shares).collect()
+}
+
+fn max_part_weights(graph: &WeightedGraph, shares: &[usize]) -> Vec<usize> {
+    let total_shares: usize = shares.iter().sum();
+    let heaviest = graph.vertex_weights.iter().copied().max().unwrap_or(0);
+    shares.iter()
+        .map(|s| {
+            let average = (graph.total_weight() * s).div_ceil(total_shares);
+            (average * (100 + IMBALANCE_PCT) / 100).max(average + heaviest / 2)
+        })
+        .collect()
+}
+
+// greedy graph growing: each part starts at the heaviest free vertex and
+// absorbs the free neighbor most connected to it until it reaches its share
+fn grow_regions(graph: &WeightedGraph, targets: &[usize]) -> Vec<usize> {
+    let n = graph.len();
+    let k = targets.len();
+    let mut parts = vec![usize::MAX; n];
+
+    for (part, target) in targets.iter().enumerate().take(k - 1) {
+        let mut weight = 0;
+        // connectivity of free vertices to the growing part
+        let mut frontier: HashMap<usize, usize> = HashMap::new();
+        while weight < *target {
+            let next = frontier.iter()
+                .max_by_key(|(v, conn)| (**conn, Reverse(**v)))
+                .map(|(v, _)| *v)
+                .or_else(|| (0..n)
+                    .filter(|v| parts[*v] == usize::MAX)
+                    .max_by_key(|v| (graph.vertex_weights[*v], Reverse(*v))));
+            let v = match next {
+                Some(v) => v,
+                None => break,
+            };
+            frontier.remove(&v);
+            parts[v] = part;
+            weight += graph.vertex_weights[v];
+            for (u, w) in &graph.adjacency[v] {
+                if parts[*u] == usize::MAX {
+                    *frontier.entry(*u).or_insert(0) += w;
+                }
+            }
+        }
+    }
+    for part in parts.iter_mut() {
+        if *part == usize::MAX {
+            *part = k - 1;
+        }
+    }
+    parts
+}
+
+// weight of edges from `v` into each part
+fn connectivity(graph: &WeightedGraph, parts: &[usize], v: usize) -> HashMap<usize, usize> {
+    let mut conn = HashMap::new();
+    for (u, w) in &graph.adjacency[v] {
+        *conn.entry(parts[*u]).or_insert(0) += w;
+    }
+    conn
+}
+
+// best admissible move of `v` as `(gain, target part)`
+fn best_move(graph: &WeightedGraph, parts: &[usize], weights: &[usize], limits: &[usize], v: usize)
+             -> Option<(isize, usize)> {
+    let from = parts[v];
+    let conn = connectivity(graph, parts, v);
+    let internal = *conn.get(&from).unwrap_or(&0) as isize;
+    let mut best: Option<(isize, usize)> = None;
+    let mut targets: Vec<(&usize, &usize)> = conn.iter().filter(|(p, _)| **p != from).collect();
+    targets.sort();
+    for (to, external) in targets {
+        if weights[*to] + graph.vertex_weights[v] > limits[*to] {
+            continue;
+        }
+        let gain = *external as isize - internal;
+        let better = match best {
+            None => true,
+            Some((bg, bt)) => (gain, Reverse(weights[*to])) > (bg, Reverse(weights[bt])),
+        };
+        if better {
+            best = Some((gain, *to));
+        }
+    }
+    best
+}
+
+fn refine(graph: &WeightedGraph, parts: &mut [usize], limits: &[usize]) {
+    rebalance_parts(graph, parts, limits);
+    for _ in 0..FM_PASSES {
+        if !fm_pass(graph, parts, limits) {
+            break;
+        }
+    }
+}
+
+/// Moves vertices out of overweight parts into the lightest part, cheapest
+/// cut first, until no part is heavier than `limit` or no move helps.
+pub fn rebalance(graph: &WeightedGraph, parts: &mut [usize], k: usize, limit: usize) {
+    rebalance_parts(graph, parts, &vec![limit; k]);
+}
+
+// candidate moves out of one part into another as `(gain, vertex,
+// version)`, kept up to date as vertices move like the heap of `fm_pass`
+type MoveHeap = BinaryHeap<(isize, Reverse<usize>, usize)>;
+
+// cut saved by moving `v` from part `from` to part `to`
+fn move_gain(graph: &WeightedGraph, parts: &[usize], v: usize, from: usize, to: usize) -> isize {
+    graph.adjacency[v].iter()
+        .map(|(u, w)| match parts[*u] {
+            p if p == to => *w as isize,
+            p if p == from => -(*w as isize),
+            _ => 0,
+        })
+        .sum()
+}
+
+// `rebalance` with a limit per part; parts are compared by how far they are
+// above their own limit
+fn rebalance_parts(graph: &WeightedGraph, parts: &mut [usize], limits: &[usize]) {
+    let k = limits.len();
+    let mut weights = graph.part_weights(parts, k);
+    let mut version = vec![0usize; graph.len()];
+    // built on first use for each (heaviest, lightest) pair
+    let mut heaps: HashMap<(usize, usize), MoveHeap> = HashMap::new();
+    loop {
+        let excess = |p: usize, weights: &[usize]| weights[p] as isize - limits[p] as isize;
+        let heaviest = (0..k).max_by_key(|p| (excess(*p, &weights), Reverse(*p))).unwrap();
+        let lightest = (0..k).min_by_key(|p| (excess(*p, &weights), *p)).unwrap();
+        if weights[heaviest] <= limits[heaviest] {
+            return;
+        }
+        let slack = (excess(heaviest, &weights) - excess(lightest, &weights)) as usize;
+        let movable = |v: usize| graph.vertex_weights[v] > 0 && graph.vertex_weights[v] < slack;
+
+        // slack only shrinks while the pair stays the same, so a vertex too
+        // heavy to move is dropped; a heap that runs dry is built again once
+        // in case the slack grew since it was built
+        let mut candidate = None;
+        for attempt in 0..2 {
+            if attempt == 1 {
+                heaps.remove(&(heaviest, lightest));
+            }
+            let mut built = false;
+            let heap = heaps.entry((heaviest, lightest)).or_insert_with(|| {
+                built = true;
+                (0..graph.len())
+                    .filter(|v| parts[*v] == heaviest)
+                    .map(|v| (move_gain(graph, parts, v, heaviest, lightest), Reverse(v), version[v]))
+                    .collect()
+            });
+            while let Some((_, Reverse(v), stamp)) = heap.pop() {
+                if stamp == version[v] && parts[v] == heaviest && movable(v) {
+                    candidate = Some(v);
+                    break;
+                }
+            }
+            if candidate.is_some() || built {
+                break;
+            }
+        }
+
+        match candidate {
+            Some(v) => {
+                parts[v] = lightest;
+                weights[heaviest] -= graph.vertex_weights[v];
+                weights[lightest] += graph.vertex_weights[v];
+                // only the gains of `v` and its neighbors changed
+                for u in graph.adjacency[v].iter().map(|(u, _)| *u).chain(Some(v)) {
+                    version[u] += 1;
+                    for ((from, to), heap) in heaps.iter_mut() {
+                        if *from == parts[u] {
+                            heap.push((move_gain(graph, parts, u, *from, *to), Reverse(u), version[u]));
+                        }
+                    }
+                }
+            }
+            // no single vertex move improves the balance
+            None => return,
+        }
+    }
+}
+
+// one Fiduccia–Mattheyses pass: greedily apply the best move of each vertex
+// once, including uphill moves, then roll back to the best prefix. Returns
+// whether the cut improved.
+fn fm_pass(graph: &WeightedGraph, parts: &mut [usize], limits: &[usize]) -> bool {
+    let n = graph.len();
+    let mut weights = graph.part_weights(parts, limits.len());
+    let mut locked = vec![false; n];
+    let mut version = vec![0usize; n];
+    let mut heap = BinaryHeap::new();
+
+    let push = |heap: &mut BinaryHeap<(isize, Reverse<usize>, usize)>,
+                parts: &[usize], weights: &[usize], version: &[usize], v: usize| {
+        if let Some((gain, _)) = best_move(graph, parts, weights, limits, v) {
+            heap.push((gain, Reverse(v), version[v]));
+        }
+    };
+    for v in 0..n {
+        if graph.adjacency[v].iter().any(|(u, _)| parts[*u] != parts[v]) {
+            push(&mut heap, parts, &weights, &version, v);
+        }
+    }
+
+    // (vertex, previous part)
+    let mut moves: Vec<(usize, usize)> = vec![];
+    let mut total_gain: isize = 0;
+    let mut best_gain: isize = 0;
+    let mut best_len = 0;
+
+    while let Some((gain, Reverse(v), stamp)) = heap.pop() {
+        if locked[v] || stamp != version[v] {
+            continue;
+        }
+        // part weights may have changed since this entry was pushed
+        let (gain_now, to) = match best_move(graph, parts, &weights, limits, v) {
+            Some(m) => m,
+            None => continue,
+        };
+        if gain_now < gain {
+            version[v] += 1;
+            heap.push((gain_now, Reverse(v), version[v]));
+            continue;
+        }
+
+        let from = parts[v];
+        locked[v] = true;
+        parts[v] = to;
+        weights[from] -= graph.vertex_weights[v];
+        weights[to] += graph.vertex_weights[v];
+        moves.push((v, from));
+        total_gain += gain_now;
+        if total_gain > best_gain {
+            best_gain = total_gain;
+            best_len = moves.len();
+        } else if moves.len() - best_len > FM_MAX_FRUITLESS_MOVES {
+            break;
+        }
+
+        for (u, _) in &graph.adjacency[v] {
+            if !locked[*u] {
+                version[*u] += 1;
+                push(&mut heap, parts, &weights, &version, *u);
+            }
+        }
+    }
+
+    for (v, from) in moves.drain(best_len..).rev() {
+        parts[v] = from;
+    }
+    best_gain > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two cliques of `size` vertices joined by a single bridge edge
+    fn two_cliques(size: usize) -> WeightedGraph {
+        let mut graph = WeightedGraph::new();
+        for _ in 0..(2 * size) {
+            graph.add_vertex(1);
+        }
+        for offset in [0, size] {
+            for a in 0..size {
+                for b in (a + 1)..size {
+                    graph.add_edge(offset + a, offset + b, 1);
+                }
+            }
+        }
+        graph.add_edge(0, size, 1);
+        graph
+    }
+
+    #[test]
+    fn test_bisect_two_cliques() {
+        let graph = two_cliques(40);
+        let parts = partition(&graph, 2);
+        assert_eq!(graph.cut(&parts), 1);
+        assert_eq!(graph.part_weights(&parts, 2), vec![40, 40]);
+    }
+
+    #[test]
+    fn test_balanced_chain() {
+        // a long path partitions into contiguous, equally sized pieces
+        let mut graph = WeightedGraph::new();
+        for v in 0..400 {
+            graph.add_vertex(1);
+            if v > 0 {
+                graph.add_edge(v - 1, v, 1);
+            }
+        }
+        let parts = partition(&graph, 4);
+        let limits = max_part_weights(&graph, &[1; 4]);
+        assert!(graph.part_weights(&parts, 4).iter().zip(&limits).all(|(w, limit)| w <= limit && *w > 0));
+        assert!(graph.cut(&parts) <= 6);
+    }
+
+    #[test]
+    fn test_more_parts_than_vertices() {
+        let mut graph = WeightedGraph::new();
+        graph.add_vertex(1);
+        graph.add_vertex(1);
+        graph.add_edge(0, 1, 1);
+        let parts = partition(&graph, 4);
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|p| *p < 4));
+        assert_ne!(parts[0], parts[1]);
+    }
+
+    #[test]
+    fn test_shares() {
+        let mut graph = WeightedGraph::new();
+        for v in 0..300 {
+            graph.add_vertex(1);
+            if v > 0 {
+                graph.add_edge(v - 1, v, 1);
+            }
+        }
+        let parts = partition_shares(&graph, &[1, 2]);
+        let weights = graph.part_weights(&parts, 2);
+        assert!((95..=105).contains(&weights[0]), "{:?}", weights);
+        assert!(graph.cut(&parts) <= 3);
+    }
+
+    #[test]
+    fn test_subgraph() {
+        let graph = two_cliques(3);
+        let sub = graph.subgraph(&[0, 1, 3]);
+        assert_eq!(sub.len(), 3);
+        // 0 - 1 inside the first clique and the 0 - 3 bridge
+        assert_eq!(sub.adjacency[0].len(), 2);
+        assert_eq!(sub.adjacency[2], vec![(0, 1)]);
+    }
+
+    #[test]
+    fn test_rebalance_everything_in_one_part() {
+        let mut graph = WeightedGraph::new();
+        for v in 0..20000 {
+            graph.add_vertex(1);
+            if v > 0 {
+                graph.add_edge(v / 2, v, 1);
+            }
+        }
+        let mut parts = vec![0; graph.len()];
+        rebalance(&graph, &mut parts, 8, 2501);
+        assert!(graph.part_weights(&parts, 8).iter().all(|w| (2499..=2501).contains(w)));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let graph = two_cliques(25);
+        assert_eq!(partition(&graph, 3), partition(&graph, 3));
+    }
+}
@@ -1,32 +1,19 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+extern crate serde;
 use serde::{Deserialize, Serialize};
 use serde::ser::SerializeStruct;
 
-pub type RENode = Rc<RefCell<ENode>>;
-#[macro_export]
-macro_rules! db {
-    ($e: expr) => {
-        $e.deref().borrow()
-    };
-    (mut, $e: expr) => {
-        $e.deref().borrow_mut()
-    };
-}
+/// Index of a node inside an `Arena`.
+pub type NodeId = usize;
 
-#[macro_export]
-macro_rules! rr {
-    ($e: expr) => {
-        Rc::new(RefCell::new($e))
-    };
-}
+/// `(idx, nth)` identity of an edge hit.
+pub type NodeKey = (u32, u8);
 
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub struct ENode {
     pub idx: u32,
     pub nth: u8,
     pub weight: usize,
-    pub children: Vec<RENode>,
+    pub children: Vec<NodeId>,
     // metadata for partitioning
     pub _cumulated: usize,
     pub _claimed: bool,
@@ -37,7 +24,7 @@ impl Serialize for ENode {
         where S: serde::Serializer
     {
 
-        let mut state = serializer.serialize_struct("ENode", 4)?;
+        let mut state = serializer.serialize_struct("ENode", 3)?;
         state.serialize_field("idx", &self.idx)?;
         state.serialize_field("nth", &self.nth)?;
         state.serialize_field("weight", &self.weight)?;
@@ -45,12 +32,23 @@ impl Serialize for ENode {
     }
 }
 
-// derserialize
+// mirrors the fields written by `Serialize`; edges travel separately
+#[derive(Deserialize)]
+#[serde(rename = "ENode")]
+struct SerializedENode {
+    idx: u32,
+    nth: u8,
+    weight: usize,
+}
+
 impl<'de> Deserialize<'de> for ENode {
     fn deserialize<D>(deserializer: D) -> Result<ENode, D::Error>
         where D: serde::Deserializer<'de>
     {
-        unreachable!();
+        let SerializedENode { idx, nth, weight } = SerializedENode::deserialize(deserializer)?;
+        let mut node = ENode::new_claimed(idx, nth);
+        node.weight = weight;
+        Ok(node)
     }
 }
 
@@ -78,4 +76,83 @@ impl ENode {
 
         }
     }
+
+    pub fn key(&self) -> NodeKey {
+        (self.idx, self.nth)
+    }
+}
+
+/// Owns every node of a graph; edges are stored as `NodeId`s into it.
+#[derive(Clone, Debug, Default)]
+pub struct Arena {
+    nodes: Vec<ENode>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Arena { nodes: vec![] }
+    }
+
+    pub fn alloc(&mut self, node: ENode) -> NodeId {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    pub fn get(&self, id: NodeId) -> &ENode {
+        &self.nodes[id]
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> &mut ENode {
+        &mut self.nodes[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &ENode)> {
+        self.nodes.iter().enumerate()
+    }
+
+    pub fn add_child(&mut self, parent: NodeId, child: NodeId) {
+        if !self.nodes[parent].children.contains(&child) {
+            self.nodes[parent].children.push(child);
+        }
+    }
+}
+
+/// Read-only access to the nodes of an execution graph.
+pub trait GraphView {
+    fn root(&self) -> NodeId;
+    fn arena(&self) -> &Arena;
+
+    /// Hits per replica of a node, sorted by replica, for graphs that count
+    /// them (`DGraph`).
+    fn counters(&self, _id: NodeId) -> &[(u32, usize)] {
+        &[]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enode_round_trip() {
+        let mut node = ENode::new_claimed(7, 3);
+        node.weight = 42;
+        node.children.push(1);
+
+        let bytes = bincode::serialize(&node).unwrap();
+        let decoded: ENode = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.key(), (7, 3));
+        assert_eq!(decoded.weight, 42);
+        assert!(decoded._claimed);
+        // edges are not part of the node encoding
+        assert!(decoded.children.is_empty());
+    }
 }
@@ -0,0 +1,141 @@
+use std::io;
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the layout of `Chunk` changes.
+pub const CHUNK_FORMAT_VERSION: u32 = 1;
+
+// bincode size of everything in a chunk but its bytes
+const CHUNK_HEADER_LEN: usize = 4 + 8 + 4 + 4 + 8;
+
+// one piece of a message
+#[derive(Serialize, Deserialize)]
+struct Chunk {
+    version: u32,
+    id: u64,
+    index: u32,
+    count: u32,
+    bytes: Vec<u8>,
+}
+
+/// The error for bytes that do not decode, e.g. a damaged packet or one of
+/// another format version.
+pub fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Splits a message of any size into packets of at most `max_len` bytes
+/// each, to be put back together by a `PacketAssembler`. `id` tells the
+/// messages of one sender apart.
+pub fn to_packets(bytes: &[u8], id: u64, max_len: usize) -> Vec<Vec<u8>> {
+    assert!(max_len > CHUNK_HEADER_LEN, "packets too small for a chunk");
+    let chunks: Vec<&[u8]> = if bytes.is_empty() {
+        vec![&[]]
+    } else {
+        bytes.chunks(max_len - CHUNK_HEADER_LEN).collect()
+    };
+    let count = chunks.len() as u32;
+    chunks.into_iter().enumerate().map(|(index, bytes)| {
+        bincode::serialize(&Chunk {
+            version: CHUNK_FORMAT_VERSION,
+            id,
+            index: index as u32,
+            count,
+            bytes: bytes.to_vec(),
+        }).unwrap()
+    }).collect()
+}
+
+/// Collects the packets of the messages of one sender and hands each
+/// message out once all of its packets arrived. Packets of a new message
+/// drop what is left of an incomplete one. A packet that does not decode
+/// or does not fit its message is an error and changes nothing.
+#[derive(Default)]
+pub struct PacketAssembler {
+    id: Option<u64>,
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+impl PacketAssembler {
+    pub fn new() -> Self {
+        PacketAssembler::default()
+    }
+
+    pub fn push(&mut self, packet: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        let version: u32 = bincode::deserialize(packet).map_err(invalid_data)?;
+        if version != CHUNK_FORMAT_VERSION {
+            return Err(invalid_data(format!("unsupported chunk format version {}", version)));
+        }
+        let chunk: Chunk = bincode::deserialize(packet).map_err(invalid_data)?;
+        let count = if self.id == Some(chunk.id) { self.chunks.len() } else { chunk.count as usize };
+        if chunk.count as usize != count || chunk.index >= chunk.count {
+            return Err(invalid_data(format!("chunk {} of {} does not fit message {}",
+                                            chunk.index, chunk.count, chunk.id)));
+        }
+
+        if self.id != Some(chunk.id) {
+            self.id = Some(chunk.id);
+            self.chunks = vec![None; count];
+        }
+        self.chunks[chunk.index as usize] = Some(chunk.bytes);
+        if self.chunks.iter().any(|c| c.is_none()) {
+            return Ok(None);
+        }
+
+        self.id = None;
+        Ok(Some(self.chunks.drain(..).flatten().flatten().collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let message: Vec<u8> = (0..10_000).map(|i| i as u8).collect();
+        let packets = to_packets(&message, 3, 4096 - 5);
+        assert_eq!(packets.len(), 3);
+        assert!(packets.iter().all(|p| p.len() <= 4096 - 5));
+
+        let mut assembler = PacketAssembler::new();
+        let mut result = None;
+        for packet in &packets {
+            assert!(result.is_none());
+            result = assembler.push(packet).unwrap();
+        }
+        assert_eq!(result.unwrap(), message);
+
+        let empty = to_packets(&[], 4, 64);
+        assert_eq!(assembler.push(&empty[0]).unwrap().unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_incomplete_message_replaced() {
+        let mut assembler = PacketAssembler::new();
+        assert!(assembler.push(&to_packets(&[1; 1000], 1, 256)[0]).unwrap().is_none());
+        assert_eq!(assembler.push(&to_packets(&[2; 10], 2, 256)[0]).unwrap().unwrap(), vec![2; 10]);
+    }
+
+    #[test]
+    fn test_bad_packets_rejected() {
+        let mut assembler = PacketAssembler::new();
+        let packets = to_packets(&[1; 1000], 1, 256);
+        assert!(assembler.push(&packets[0]).unwrap().is_none());
+
+        assert!(assembler.push(&[1, 2]).is_err());
+        let mut other_version = packets[1].clone();
+        other_version[0] = 9;
+        assert!(assembler.push(&other_version).is_err());
+        for (index, count) in [(7, 7), (1, 9), (0, 0)] {
+            let chunk = Chunk { version: CHUNK_FORMAT_VERSION, id: 1, index, count, bytes: vec![] };
+            assert!(assembler.push(&bincode::serialize(&chunk).unwrap()).is_err());
+        }
+
+        // none of them disturbed the message in progress
+        let mut result = None;
+        for packet in &packets[1..] {
+            result = assembler.push(packet).unwrap();
+        }
+        assert_eq!(result.unwrap(), vec![1; 1000]);
+    }
+}
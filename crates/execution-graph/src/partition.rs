@@ -1,29 +1,801 @@
-use std::collections::HashMap;
-use db;
-use nodes::RENode;
-use std::ops::Deref;
+use std::collections::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use codemap::{CodeMap, Granularity};
+use context::edge_of;
+use dominator::DominatorCut;
+use multilevel::{self, WeightedGraph};
+use nodes::{GraphView, NodeId, NodeKey};
 
-#[derive(Clone, Debug)]
+/// Bumped whenever the layout of `PartitionPlan` changes.
+pub const PLAN_FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PartitionPlan {
-    pub plan: Vec<RENode>,
+    pub plan: Vec<NodeKey>,
     pub weight: usize,
-    pub dependencies: HashMap<usize, Vec<usize>>,
+    // boundary edges leaving the plan: source -> [(target, owning plan)]
+    pub dependencies: HashMap<NodeKey, Vec<(NodeKey, usize)>>,
 }
 
 impl PartitionPlan {
     pub fn merge(&mut self, other: &PartitionPlan) {
-        self.plan = vec![self.plan.clone(), other.plan.clone()].concat();
+        self.plan = [self.plan.clone(), other.plan.clone()].concat();
         self.weight += other.weight;
         for (node, deps) in &other.dependencies {
-            self.dependencies.insert(node.clone(), deps.clone());
+            let entry = self.dependencies.entry(*node).or_default();
+            for dep in deps {
+                if !entry.contains(dep) {
+                    entry.push(*dep);
+                }
+            }
         }
     }
 
-    pub fn serialize(&self) -> Vec<usize> {
+    /// Edge indices owned by this plan.
+    pub fn indices(&self) -> Vec<usize> {
         let mut result = vec![];
-        for node in &self.plan {
-            result.push(db!(node).idx as usize);
+        for (idx, _) in &self.plan {
+            result.push(*idx as usize);
         }
         result
     }
-}
\ No newline at end of file
+
+    pub fn serialize(&self) -> Vec<u8> {
+        bincode::serialize(&(PLAN_FORMAT_VERSION, self)).unwrap()
+    }
+
+    pub fn deserialize(bytes: Vec<u8>) -> Self {
+        let version: u32 = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(version, PLAN_FORMAT_VERSION, "unsupported PartitionPlan format version");
+
+        let (_, plan): (u32, PartitionPlan) = bincode::deserialize(&bytes).unwrap();
+        plan
+    }
+}
+
+/// The plan owning every node the plans list. A node listed in several
+/// plans is owned by the first of them.
+pub fn owners(plans: &[PartitionPlan]) -> HashMap<NodeKey, usize> {
+    let mut owner = HashMap::new();
+    for (i, plan) in plans.iter().enumerate() {
+        for key in &plan.plan {
+            owner.entry(*key).or_insert(i);
+        }
+    }
+    owner
+}
+
+/// Fills every plan's `dependencies` with the graph edges that leave it,
+/// which lead to their `owners`.
+pub fn fill_dependencies(graph: &dyn GraphView, plans: &mut [PartitionPlan]) {
+    let owner = owners(plans);
+
+    let arena = graph.arena();
+    for plan in plans.iter_mut() {
+        plan.dependencies.clear();
+        let members: HashSet<NodeKey> = plan.plan.iter().copied().collect();
+        for (_, node) in arena.iter() {
+            if !members.contains(&node.key()) {
+                continue;
+            }
+            for child in &node.children {
+                let target = arena.get(*child).key();
+                if members.contains(&target) {
+                    continue;
+                }
+                let dep = match owner.get(&target) {
+                    Some(target_owner) => (target, *target_owner),
+                    // not assigned to any plan
+                    None => continue,
+                };
+                let entry = plan.dependencies.entry(node.key()).or_default();
+                if !entry.contains(&dep) {
+                    entry.push(dep);
+                }
+            }
+        }
+    }
+}
+
+fn empty_plans(k: usize) -> Vec<PartitionPlan> {
+    (0..k).map(|_| PartitionPlan {
+        plan: vec![],
+        weight: 0,
+        dependencies: HashMap::new(),
+    }).collect()
+}
+
+/// A way of splitting an execution graph into `k` plans.
+pub trait PartitionStrategy {
+    fn partition(&self, graph: &dyn GraphView, k: usize) -> Vec<PartitionPlan>;
+
+    /// Groups of nodes the strategy never splits, as a unit per node in
+    /// arena order (`None` for nodes free to go anywhere), so `reassign_units`
+    /// keeps them whole too. By default every node is free.
+    fn units(&self, graph: &dyn GraphView, _k: usize) -> Vec<Option<usize>> {
+        vec![None; graph.arena().len()]
+    }
+
+    /// A group per plan; `stable_partition` only swaps plans of one group.
+    /// By default all plans are alike.
+    fn groups(&self, k: usize) -> Vec<usize> {
+        vec![0; k]
+    }
+}
+
+/// Looks up a strategy by its configuration name.
+pub fn strategy_from_name(name: &str) -> Option<Box<dyn PartitionStrategy + Send + Sync>> {
+    match name {
+        "chunk" => Some(Box::new(CountChunking)),
+        "leaf" => Some(Box::new(LeafPacking)),
+        "multilevel" => Some(Box::new(Multilevel)),
+        "dominator" => Some(Box::new(DominatorCut)),
+        _ => None,
+    }
+}
+
+/// Chops the nodes, in creation order, into `k` buckets whose node counts
+/// differ by at most one. Ignores edges and weights.
+pub struct CountChunking;
+
+impl PartitionStrategy for CountChunking {
+    fn partition(&self, graph: &dyn GraphView, k: usize) -> Vec<PartitionPlan> {
+        let arena = graph.arena();
+        let mut partitions = empty_plans(k);
+        let (base, extra) = (arena.len() / k, arena.len() % k);
+        let mut nodes = arena.iter();
+        for (i, partition) in partitions.iter_mut().enumerate() {
+            let size = base + if i < extra { 1 } else { 0 };
+            for (_, node) in nodes.by_ref().take(size) {
+                partition.plan.push(node.key());
+                partition.weight += node.weight;
+            }
+        }
+        fill_dependencies(graph, &mut partitions);
+        partitions
+    }
+}
+
+/// Turns every root-to-leaf path into a plan weighted by its cumulative
+/// weight, then greedily packs the heaviest paths into `k` plans. Graphs
+/// that are not trees are walked along a depth-first spanning tree.
+pub struct LeafPacking;
+
+impl LeafPacking {
+    // one plan per leaf: its path from the root and cumulative weight
+    fn leaves(graph: &dyn GraphView) -> Vec<PartitionPlan> {
+        let arena = graph.arena();
+        let mut visited = vec![false; arena.len()];
+        let mut leaves = vec![];
+        // (node, cumulative weight of its parent, path to its parent)
+        let mut stack: Vec<(NodeId, usize, Vec<NodeKey>)> = vec![(graph.root(), 0, vec![])];
+        visited[graph.root()] = true;
+        while let Some((id, parent_weight, mut trace)) = stack.pop() {
+            let node = arena.get(id);
+            let cumulated = parent_weight + node.weight;
+            trace.push(node.key());
+
+            let children: Vec<NodeId> = node.children.iter().copied()
+                .filter(|child| !visited[*child])
+                .collect();
+            if children.is_empty() {
+                leaves.push(PartitionPlan {
+                    plan: trace,
+                    weight: cumulated,
+                    dependencies: HashMap::new(),
+                });
+                continue;
+            }
+            for child in &children {
+                visited[*child] = true;
+            }
+            // pushed in reverse so children are visited in order
+            for child in children.into_iter().rev() {
+                stack.push((child, cumulated, trace.clone()));
+            }
+        }
+        leaves
+    }
+}
+
+impl PartitionStrategy for LeafPacking {
+    fn partition(&self, graph: &dyn GraphView, k: usize) -> Vec<PartitionPlan> {
+        let mut leaves = Self::leaves(graph);
+
+        let total_weight: usize = leaves.iter().map(|leaf| leaf.weight).sum();
+        let approx_partition_weight = total_weight / k;
+
+        // merge until we have k partitions
+        let mut partitions: Vec<PartitionPlan> = vec![];
+
+        // we 'll use a greedy algorithm to merge leaves
+
+        // first, sort leaves by weight
+        leaves.sort_by_key(|leaf| std::cmp::Reverse(leaf.weight));
+        // then, merge leaves until we have k partitions
+        for leaf in leaves {
+            let mut merged = false;
+            for partition in &mut partitions {
+                if partition.weight + leaf.weight <= approx_partition_weight {
+                    partition.merge(&leaf);
+                    merged = true;
+                    break;
+                }
+            }
+            if !merged {
+                partitions.push(leaf);
+            }
+        }
+        // if we still have more than k partitions, we merge from last to first
+        while partitions.len() > k {
+            let last_p = partitions.pop().expect("no partition to pop");
+            partitions.last_mut().unwrap().merge(&last_p);
+        }
+        // fewer leaves than parts: the rest stay empty
+        partitions.extend(empty_plans(k.saturating_sub(partitions.len())));
+        fill_dependencies(graph, &mut partitions);
+        partitions
+    }
+}
+
+// arena ids are dense and creation-ordered, so they double as vertex ids
+// and keep results deterministic
+fn weighted_graph(graph: &dyn GraphView) -> WeightedGraph {
+    let arena = graph.arena();
+    let mut weighted = WeightedGraph::new();
+    for (_, node) in arena.iter() {
+        // every node costs something to own, even before it is hit
+        weighted.add_vertex(node.weight.max(1));
+    }
+    for (id, node) in arena.iter() {
+        for child in &node.children {
+            weighted.add_edge(id, *child, 1);
+        }
+    }
+    weighted
+}
+
+fn plans_from_parts(graph: &dyn GraphView, parts: &[usize], k: usize) -> Vec<PartitionPlan> {
+    let mut partitions = empty_plans(k);
+    for (id, node) in graph.arena().iter() {
+        let partition = &mut partitions[parts[id]];
+        partition.plan.push(node.key());
+        partition.weight += node.weight;
+    }
+    fill_dependencies(graph, &mut partitions);
+    partitions
+}
+
+/// Balanced, cut-minimizing partitioning, see `multilevel::partition`.
+pub struct Multilevel;
+
+impl PartitionStrategy for Multilevel {
+    fn partition(&self, graph: &dyn GraphView, k: usize) -> Vec<PartitionPlan> {
+        let parts = multilevel::partition(&weighted_graph(graph), k);
+        plans_from_parts(graph, &parts, k)
+    }
+}
+
+/// Two-level partitioning for ranks spread over several hosts: the graph is
+/// first split across hosts, in proportion to how many ranks each has, and
+/// then each host's piece is split across its ranks. Plans are numbered host
+/// by host, so with `hosts: vec![2, 3]` plans 0 and 1 belong to the first
+/// host and plans 2 to 4 to the second. Edges between hosts are cut first
+/// and least, which keeps most forwarded testcases on the same machine.
+/// When `hosts` do not add up to `k` ranks, the split is `Multilevel`'s.
+pub struct Hierarchical {
+    // number of ranks on each host
+    pub hosts: Vec<usize>,
+}
+
+impl Hierarchical {
+    /// Groups `(rank, host name)` pairs by host, in order of first
+    /// appearance. Returns the strategy and the rank each plan goes to.
+    pub fn from_hosts(ranks: &[(u32, String)]) -> (Self, Vec<u32>) {
+        let mut names: Vec<&String> = vec![];
+        let mut members: Vec<Vec<u32>> = vec![];
+        for (rank, name) in ranks {
+            match names.iter().position(|n| *n == name) {
+                Some(host) => members[host].push(*rank),
+                None => {
+                    names.push(name);
+                    members.push(vec![*rank]);
+                }
+            }
+        }
+        let hosts = members.iter().map(|m| m.len()).collect();
+        (Hierarchical { hosts }, members.concat())
+    }
+}
+
+impl PartitionStrategy for Hierarchical {
+    fn partition(&self, graph: &dyn GraphView, k: usize) -> Vec<PartitionPlan> {
+        if self.hosts.iter().sum::<usize>() != k {
+            return Multilevel.partition(graph, k);
+        }
+        let weighted = weighted_graph(graph);
+        let host_of = multilevel::partition_shares(&weighted, &self.hosts);
+
+        let mut parts = vec![0; weighted.len()];
+        let mut first = 0;
+        for (host, ranks) in self.hosts.iter().enumerate() {
+            let members: Vec<usize> = (0..weighted.len()).filter(|v| host_of[*v] == host).collect();
+            let local = multilevel::partition(&weighted.subgraph(&members), *ranks);
+            for (v, part) in members.iter().zip(local) {
+                parts[*v] = first + part;
+            }
+            first += ranks;
+        }
+        plans_from_parts(graph, &parts, k)
+    }
+
+    fn groups(&self, k: usize) -> Vec<usize> {
+        if self.hosts.iter().sum::<usize>() != k {
+            return vec![0; k];
+        }
+        self.hosts.iter().enumerate().flat_map(|(host, ranks)| vec![host; *ranks]).collect()
+    }
+}
+
+/// Assigns whole functions or source files to plans, so every rank owns a
+/// coherent piece of the target. Units are balanced by the weight of their
+/// nodes and placed to cut as few calls between them as possible; nodes
+/// outside the code map (e.g. the root) are placed on their own.
+pub struct CodeUnits {
+    pub map: CodeMap,
+    pub granularity: Granularity,
+}
+
+impl PartitionStrategy for CodeUnits {
+    fn partition(&self, graph: &dyn GraphView, k: usize) -> Vec<PartitionPlan> {
+        partition_units(graph, &self.units(graph, k), k)
+    }
+
+    fn units(&self, graph: &dyn GraphView, _k: usize) -> Vec<Option<usize>> {
+        graph.arena().iter()
+            .map(|(_, node)| self.map.unit(edge_of(node.idx), self.granularity))
+            .collect()
+    }
+}
+
+/// Partitions a graph whose nodes are grouped into units that must not be
+/// split: every unit is contracted into one vertex weighing as much as its
+/// nodes, and the contracted graph is split by `multilevel::partition`.
+/// Nodes without a unit are placed on their own.
+pub fn partition_units(graph: &dyn GraphView, units: &[Option<usize>], k: usize) -> Vec<PartitionPlan> {
+    let (contracted, vertex) = contract(graph, units);
+    let unit_parts = multilevel::partition(&contracted, k);
+    let parts: Vec<usize> = vertex.iter().map(|v| unit_parts[*v]).collect();
+    plans_from_parts(graph, &parts, k)
+}
+
+/// The graph with every unit contracted into one vertex, and the vertex of
+/// every node. Without units this is `weighted_graph`.
+fn contract(graph: &dyn GraphView, units: &[Option<usize>]) -> (WeightedGraph, Vec<usize>) {
+    let arena = graph.arena();
+    // one vertex per unit
+    let mut contracted = WeightedGraph::new();
+    let mut unit_vertex: HashMap<usize, usize> = HashMap::new();
+    let mut vertex = Vec::with_capacity(arena.len());
+    for (id, node) in arena.iter() {
+        let v = match units[id] {
+            Some(unit) => match unit_vertex.get(&unit) {
+                Some(v) => *v,
+                None => {
+                    let v = contracted.add_vertex(0);
+                    unit_vertex.insert(unit, v);
+                    v
+                }
+            },
+            None => contracted.add_vertex(0),
+        };
+        contracted.vertex_weights[v] += node.weight.max(1);
+        vertex.push(v);
+    }
+    for (id, node) in arena.iter() {
+        for child in &node.children {
+            contracted.add_edge(vertex[id], vertex[*child], 1);
+        }
+    }
+    (contracted, vertex)
+}
+
+/// Result of `reassign`.
+#[derive(Clone, Debug)]
+pub struct Reassignment {
+    pub plans: Vec<PartitionPlan>,
+    // previously owned nodes that changed owner
+    pub migrated: usize,
+}
+
+/// Updates `previous` for a grown graph while keeping ownership stable.
+/// Nodes keep their owner; new nodes (and nodes of plans `>= k`) join the
+/// plan most of their neighbors belong to. Nodes only migrate when the
+/// heaviest plan exceeds the average by more than `max_imbalance_pct`.
+pub fn reassign(graph: &dyn GraphView, previous: &[PartitionPlan], k: usize,
+                max_imbalance_pct: usize) -> Reassignment {
+    reassign_units(graph, previous, k, max_imbalance_pct, &vec![None; graph.arena().len()])
+}
+
+/// `reassign` keeping every unit of `units` (see `PartitionStrategy::units`)
+/// on one plan: a unit stays with the plan owning most of its weight, new
+/// nodes join their unit, and imbalance is fixed by moving whole units.
+pub fn reassign_units(graph: &dyn GraphView, previous: &[PartitionPlan], k: usize,
+                      max_imbalance_pct: usize, units: &[Option<usize>]) -> Reassignment {
+    let owner = owners(&previous[..k.min(previous.len())]);
+
+    let (weighted, vertex) = contract(graph, units);
+    let arena = graph.arena();
+    let before: Vec<Option<usize>> = arena.iter()
+        .map(|(_, node)| owner.get(&node.key()).copied())
+        .collect();
+    // every vertex starts with the plan holding most of its previous weight
+    let mut held: Vec<HashMap<usize, usize>> = vec![HashMap::new(); weighted.len()];
+    for ((id, node), part) in arena.iter().zip(&before) {
+        if let Some(part) = part {
+            *held[vertex[id]].entry(*part).or_insert(0) += node.weight.max(1);
+        }
+    }
+    let mut parts: Vec<usize> = held.iter()
+        .map(|held| held.iter()
+            .max_by_key(|(part, weight)| (**weight, std::cmp::Reverse(**part)))
+            .map_or(usize::MAX, |(part, _)| *part))
+        .collect();
+
+    let mut weights = vec![0; k];
+    for (v, part) in parts.iter().enumerate() {
+        if *part != usize::MAX {
+            weights[*part] += weighted.vertex_weights[v];
+        }
+    }
+    for v in 0..parts.len() {
+        if parts[v] != usize::MAX {
+            continue;
+        }
+        let mut affinity = vec![0; k];
+        for (u, w) in &weighted.adjacency[v] {
+            if parts[*u] != usize::MAX {
+                affinity[parts[*u]] += w;
+            }
+        }
+        let part = (0..k)
+            .max_by_key(|p| (affinity[*p], std::cmp::Reverse(weights[*p]), std::cmp::Reverse(*p)))
+            .unwrap();
+        parts[v] = part;
+        weights[part] += weighted.vertex_weights[v];
+    }
+
+    let average = weighted.total_weight().div_ceil(k);
+    let limit = average * (100 + max_imbalance_pct) / 100;
+    if weights.iter().any(|w| *w > limit) {
+        multilevel::rebalance(&weighted, &mut parts, k, limit);
+    }
+
+    let parts: Vec<usize> = vertex.iter().map(|v| parts[*v]).collect();
+    let migrated = before.iter().zip(&parts)
+        .filter(|(before, after)| matches!(before, Some(p) if *p != **after))
+        .count();
+    Reassignment {
+        plans: plans_from_parts(graph, &parts, k),
+        migrated,
+    }
+}
+
+/// Runs `strategy` from scratch and numbers its plans so they overlap the
+/// `previous` plans as much as possible, matched greedily by the weight
+/// they share. Re-running a strategy every epoch thus keeps what it decides
+/// without shuffling every node between workers. Plans only trade places
+/// within their `PartitionStrategy::groups`.
+pub fn stable_partition(strategy: &dyn PartitionStrategy, graph: &dyn GraphView,
+                        previous: &[PartitionPlan], k: usize) -> Reassignment {
+    let owner = owners(&previous[..k.min(previous.len())]);
+    let fresh = strategy.partition(graph, k);
+    let fresh_owner = owners(&fresh);
+
+    // weight plan `new` shares with previous plan `old`
+    let mut shared: HashMap<(usize, usize), usize> = HashMap::new();
+    for (_, node) in graph.arena().iter() {
+        if let (Some(old), Some(new)) = (owner.get(&node.key()), fresh_owner.get(&node.key())) {
+            *shared.entry((*new, *old)).or_insert(0) += node.weight.max(1);
+        }
+    }
+    let mut pairs: Vec<((usize, usize), usize)> = shared.into_iter().collect();
+    pairs.sort_by_key(|((new, old), weight)| (std::cmp::Reverse(*weight), *new, *old));
+    let groups = strategy.groups(k);
+    let mut slot: Vec<Option<usize>> = vec![None; k];
+    let mut taken = vec![false; k];
+    for ((new, old), _) in pairs {
+        if slot[new].is_none() && !taken[old] && groups[new] == groups[old] {
+            slot[new] = Some(old);
+            taken[old] = true;
+        }
+    }
+    for new in 0..k {
+        if slot[new].is_none() {
+            let old = (0..k).find(|old| !taken[*old] && groups[*old] == groups[new]).unwrap();
+            slot[new] = Some(old);
+            taken[old] = true;
+        }
+    }
+    let slot: Vec<usize> = slot.into_iter().map(Option::unwrap).collect();
+
+    let mut plans = empty_plans(k);
+    for (new, plan) in fresh.into_iter().enumerate() {
+        plans[slot[new]] = plan;
+    }
+    fill_dependencies(graph, &mut plans);
+    let migrated = fresh_owner.iter()
+        .filter(|(key, new)| owner.get(key).is_some_and(|old| *old != slot[**new]))
+        .count();
+    Reassignment { plans, migrated }
+}
+
+/// `reassign` for a changing set of workers. `previous[i]` is owned by
+/// `previous_workers[i]`; the result has one plan per entry of `workers`,
+/// in that order. Surviving workers keep their plans, the nodes of workers
+/// that left join the live plan most of their neighbors are in, and workers
+/// that joined start empty. Beyond that, nodes only move as far as needed
+/// to bring every plan within `max_imbalance_pct` of the average, and
+/// `units` stay whole as in `reassign_units`. Nodes of workers that left
+/// count as migrated.
+pub fn reassign_workers(graph: &dyn GraphView, previous: &[PartitionPlan], previous_workers: &[u32],
+                        workers: &[u32], max_imbalance_pct: usize, units: &[Option<usize>]) -> Reassignment {
+    assert_eq!(previous.len(), previous_workers.len(), "every plan needs a worker");
+    let mut aligned = empty_plans(workers.len());
+    let mut orphaned: HashSet<NodeKey> = HashSet::new();
+    for (plan, worker) in previous.iter().zip(previous_workers) {
+        match workers.iter().position(|w| w == worker) {
+            Some(i) => aligned[i] = plan.clone(),
+            None => orphaned.extend(plan.plan.iter().copied()),
+        }
+    }
+
+    let mut result = reassign_units(graph, &aligned, workers.len(), max_imbalance_pct, units);
+    result.migrated += graph.arena().iter().filter(|(_, node)| orphaned.contains(&node.key())).count();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dgraph::DGraph;
+    use egraph::ETree;
+    use fixtures::sample;
+
+    #[test]
+    fn test_count_chunking_keeps_remainder() {
+        let graph = sample();
+        let partitions = CountChunking.partition(&graph, 4);
+        assert_eq!(partitions.iter().map(|p| p.plan.len()).collect::<Vec<_>>(), vec![2, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_leaf_packing_paths() {
+        let graph = sample();
+        let partitions = LeafPacking.partition(&graph, 3);
+        assert_eq!(partitions.len(), 3);
+        // every plan is one or more root-to-leaf paths
+        for partition in &partitions {
+            assert_eq!(partition.plan[0], (0, 0));
+        }
+    }
+
+    #[test]
+    fn test_leaf_packing_pads_to_k() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0)]);
+        let partitions = LeafPacking.partition(&graph, 3);
+        assert_eq!(partitions.len(), 3);
+        assert!(partitions[1..].iter().all(|p| p.plan.is_empty()));
+
+        let mut tree = ETree::default();
+        tree.add_trace(vec![(1, 0), (2, 0)]);
+        assert_eq!(tree.partition(3).len(), 3);
+    }
+
+    #[test]
+    fn test_strategies_cover_graph() {
+        let graph = sample();
+        for name in ["chunk", "leaf", "multilevel"] {
+            let strategy = strategy_from_name(name).unwrap();
+            let partitions = strategy.partition(&graph, 2);
+            assert_eq!(partitions.len(), 2, "{}", name);
+            for idx in 1..6 {
+                assert!(partitions.iter().any(|p| p.plan.contains(&(idx, 0))), "{}", name);
+            }
+        }
+        assert!(strategy_from_name("unknown").is_none());
+    }
+
+    #[test]
+    fn test_hierarchical() {
+        // two loosely joined halves, one per host
+        let mut graph = DGraph::new();
+        for half in [10, 20] {
+            for i in 0..6 {
+                graph.add_trace(vec![(half, 0), (half + 1 + i, 0), (half + 7 + i % 3, 0)]);
+            }
+        }
+        let ranks: Vec<(u32, String)> = vec![
+            (1, "a".to_string()), (2, "b".to_string()), (3, "a".to_string()), (4, "b".to_string()),
+        ];
+        let (strategy, order) = Hierarchical::from_hosts(&ranks);
+        assert_eq!(strategy.hosts, vec![2, 2]);
+        assert_eq!(order, vec![1, 3, 2, 4]);
+
+        let partitions = strategy.partition(&graph, 4);
+        assert_eq!(partitions.len(), 4);
+        let host = |key: NodeKey| partitions.iter().position(|p| p.plan.contains(&key)).unwrap() / 2;
+        for half in [10, 20] {
+            assert!((half..half + 10).all(|idx| host((idx, 0)) == host((half, 0))));
+        }
+        assert_ne!(host((10, 0)), host((20, 0)));
+
+        // re-run with the plans swapped within and across hosts: the plans
+        // come back in place, and never move to the other host
+        let previous = vec![partitions[1].clone(), partitions[0].clone(), partitions[3].clone(), partitions[2].clone()];
+        let result = stable_partition(&strategy, &graph, &previous, 4);
+        assert_eq!(result.migrated, 0);
+        let previous = vec![partitions[2].clone(), partitions[3].clone(), partitions[0].clone(), partitions[1].clone()];
+        let result = stable_partition(&strategy, &graph, &previous, 4);
+        assert_eq!(result.plans[0].plan, partitions[0].plan);
+
+        // a worker timed out: no panic, just no host grouping
+        assert_eq!(strategy.partition(&graph, 3).len(), 3);
+    }
+
+    #[test]
+    fn test_code_units() {
+        use codemap::{PcTableEntry, Symbol};
+        // edges 1-3 are one function, 4-5 another, everything else a third
+        let table: Vec<PcTableEntry> = (0..8)
+            .map(|i| PcTableEntry { pc: i, flags: if i == 0 || i == 4 || i == 6 { 1 } else { 0 } })
+            .collect();
+        let map = CodeMap::from_pc_table(&table, |pc| Symbol {
+            function: format!("f{}", pc),
+            file: "a.c".to_string(),
+        });
+        let graph = sample();
+        let strategy = CodeUnits { map, granularity: Granularity::Function };
+        let partitions = strategy.partition(&graph, 2);
+        let owner = |idx| partitions.iter().position(|p| p.plan.contains(&(idx, 0))).unwrap();
+        assert!((1..4).all(|idx| owner(idx) == owner(1)));
+        assert_eq!(owner(4), owner(5));
+
+        // everything is in one file, which cannot be split
+        let strategy = CodeUnits { granularity: Granularity::File, ..strategy };
+        let sizes: Vec<usize> = strategy.partition(&graph, 2).iter().map(|p| p.plan.len()).collect();
+        assert!(sizes.contains(&6));
+    }
+
+    #[test]
+    fn test_dependencies_are_boundary_edges() {
+        let graph = sample();
+        let partitions = CountChunking.partition(&graph, 2);
+        // creation order: [0, 1, 2] and [3, 4, 5]
+        assert_eq!(partitions[0].plan, vec![(0, 0), (1, 0), (2, 0)]);
+        assert_eq!(partitions[0].dependencies.len(), 1);
+        assert_eq!(partitions[0].dependencies[&(1, 0)], vec![((3, 0), 1)]);
+        // edges inside the plan are not dependencies
+        assert!(partitions[1].dependencies.is_empty());
+    }
+
+    #[test]
+    fn test_reassign_keeps_owners() {
+        let mut graph = sample();
+        let previous = Multilevel.partition(&graph, 2);
+        graph.add_trace(vec![(1, 0), (3, 0), (6, 0)]);
+
+        let result = reassign(&graph, &previous, 2, 50);
+        assert_eq!(result.migrated, 0);
+        for (before, after) in previous.iter().zip(&result.plans) {
+            assert!(before.plan.iter().all(|key| after.plan.contains(key)));
+        }
+        // the new node follows its parent
+        let owner = |key| result.plans.iter().position(|p| p.plan.contains(&key)).unwrap();
+        assert_eq!(owner((6, 0)), owner((3, 0)));
+    }
+
+    #[test]
+    fn test_reassign_migrates_when_imbalanced() {
+        let graph = sample();
+        // everything on plan 0
+        let mut previous = empty_plans(2);
+        previous[0] = PartitionPlan {
+            plan: graph.arena().iter().map(|(_, node)| node.key()).collect(),
+            weight: 0,
+            dependencies: HashMap::new(),
+        };
+
+        let result = reassign(&graph, &previous, 2, 10);
+        assert!(result.migrated > 0);
+        assert!(!result.plans[1].plan.is_empty());
+        let moved: usize = result.plans[1].plan.len();
+        assert_eq!(moved, result.migrated);
+    }
+
+    #[test]
+    fn test_reassign_keeps_units_whole() {
+        use codemap::{PcTableEntry, Symbol};
+        // ten functions of four edges each
+        let table: Vec<PcTableEntry> = (0..40)
+            .map(|i| PcTableEntry { pc: i, flags: if i % 4 == 0 { 1 } else { 0 } })
+            .collect();
+        let map = CodeMap::from_pc_table(&table, |pc| Symbol {
+            function: format!("f{}", pc),
+            file: "a.c".to_string(),
+        });
+        let strategy = CodeUnits { map, granularity: Granularity::Function };
+        let mut graph = DGraph::new();
+        for i in 1..8 {
+            graph.add_trace(vec![(i, 0), (i + 1, 0)]);
+        }
+        let mut plans = strategy.partition(&graph, 3);
+
+        for round in 1..6u32 {
+            // new nodes, with the new weight piling up in a few functions
+            for i in 0..20 {
+                let idx = (i * 7 + round * 3) % 40;
+                graph.add_trace(vec![(round % 4, 0), (idx, (round % 2) as u8), ((idx + 1) % 40, 0)]);
+            }
+            let units = strategy.units(&graph, 3);
+            plans = reassign_units(&graph, &plans, 3, 10, &units).plans;
+
+            let mut owner_of_unit: HashMap<usize, usize> = HashMap::new();
+            for (i, plan) in plans.iter().enumerate() {
+                for key in &plan.plan {
+                    let unit = strategy.map.unit(edge_of(key.0), Granularity::Function).unwrap();
+                    assert_eq!(*owner_of_unit.entry(unit).or_insert(i), i, "round {} split f{}", round, unit);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_stable_partition_keeps_numbering() {
+        let graph = sample();
+        let mut previous = Multilevel.partition(&graph, 2);
+        previous.reverse();
+
+        let result = stable_partition(&Multilevel, &graph, &previous, 2);
+        assert_eq!(result.migrated, 0);
+        for (before, after) in previous.iter().zip(&result.plans) {
+            assert_eq!(before.plan, after.plan);
+        }
+    }
+
+    #[test]
+    fn test_reassign_workers() {
+        let mut graph = DGraph::new();
+        for i in 0..30 {
+            graph.add_trace(vec![(1, 0), (2 + i % 3, 0), (10 + i, 0)]);
+        }
+        let plans = Multilevel.partition(&graph, 3);
+        let owned = |plans: &[PartitionPlan], i: usize| plans[i].plan.iter().copied().collect::<HashSet<_>>();
+
+        // worker 2 leaves: 1 and 3 keep everything they had
+        let result = reassign_workers(&graph, &plans, &[1, 2, 3], &[3, 1], 20, &vec![None; graph.len()]);
+        assert_eq!(result.plans.len(), 2);
+        assert!(owned(&result.plans, 0).is_superset(&owned(&plans, 2)));
+        assert!(owned(&result.plans, 1).is_superset(&owned(&plans, 0)));
+        assert_eq!(result.migrated, plans[1].plan.len());
+        assert_eq!(result.plans.iter().map(|p| p.plan.len()).sum::<usize>(), graph.len());
+
+        // worker 4 joins and takes a share from the others
+        let joined = reassign_workers(&graph, &result.plans, &[3, 1], &[3, 1, 4], 20, &vec![None; graph.len()]);
+        assert_eq!(joined.plans.len(), 3);
+        assert!(!joined.plans[2].plan.is_empty());
+        assert!(joined.migrated >= joined.plans[2].plan.len());
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let graph = sample();
+        for plan in Multilevel.partition(&graph, 2) {
+            let decoded = PartitionPlan::deserialize(plan.serialize());
+            assert_eq!(decoded.plan, plan.plan);
+            assert_eq!(decoded.weight, plan.weight);
+            assert_eq!(decoded.dependencies, plan.dependencies);
+        }
+    }
+}
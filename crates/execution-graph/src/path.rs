@@ -0,0 +1,113 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use nodes::{GraphView, NodeId, NodeKey};
+
+fn keys(graph: &dyn GraphView, previous: &[Option<NodeId>], target: NodeId) -> Vec<NodeKey> {
+    let mut path = vec![graph.arena().get(target).key()];
+    let mut id = target;
+    while let Some(parent) = previous[id] {
+        path.push(graph.arena().get(parent).key());
+        id = parent;
+    }
+    path.reverse();
+    path
+}
+
+/// Path with the fewest edges from the root to `target`, both included, or
+/// `None` if `target` cannot be reached.
+pub fn shortest_path(graph: &dyn GraphView, target: NodeId) -> Option<Vec<NodeKey>> {
+    let arena = graph.arena();
+    let mut previous: Vec<Option<NodeId>> = vec![None; arena.len()];
+    let mut visited = vec![false; arena.len()];
+    let mut queue = VecDeque::new();
+    visited[graph.root()] = true;
+    queue.push_back(graph.root());
+    while let Some(id) = queue.pop_front() {
+        if id == target {
+            return Some(keys(graph, &previous, target));
+        }
+        for child in &arena.get(id).children {
+            if !visited[*child] {
+                visited[*child] = true;
+                previous[*child] = Some(id);
+                queue.push_back(*child);
+            }
+        }
+    }
+    None
+}
+
+/// Path from the root to `target` whose least hit node is hit the most,
+/// i.e. the way most executions get there; among those, the one with the
+/// fewest edges. `None` if `target` cannot be reached.
+pub fn heaviest_path(graph: &dyn GraphView, target: NodeId) -> Option<Vec<NodeKey>> {
+    let arena = graph.arena();
+    let mut previous: Vec<Option<NodeId>> = vec![None; arena.len()];
+    let mut best: Vec<Option<(usize, Reverse<usize>)>> = vec![None; arena.len()];
+    let mut done = vec![false; arena.len()];
+    let mut heap = BinaryHeap::new();
+    let root = graph.root();
+    // every path starts at the root, its weight says nothing
+    best[root] = Some((usize::MAX, Reverse(0)));
+    heap.push((usize::MAX, Reverse(0), root));
+    while let Some((width, Reverse(len), id)) = heap.pop() {
+        if done[id] {
+            continue;
+        }
+        done[id] = true;
+        if id == target {
+            return Some(keys(graph, &previous, target));
+        }
+        for child in &arena.get(id).children {
+            let candidate = (width.min(arena.get(*child).weight), Reverse(len + 1));
+            if !done[*child] && best[*child].is_none_or(|b| candidate > b) {
+                best[*child] = Some(candidate);
+                previous[*child] = Some(id);
+                heap.push((candidate.0, candidate.1, *child));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dgraph::DGraph;
+
+    fn two_ways() -> DGraph {
+        let mut graph = DGraph::new();
+        // a short, rarely taken way to 9, and a longer, hot one
+        graph.add_trace(vec![(1, 0), (4, 0), (9, 0)]);
+        for _ in 0..5 {
+            graph.add_trace(vec![(1, 0), (2, 0), (3, 0), (9, 0)]);
+        }
+        graph
+    }
+
+    #[test]
+    fn test_shortest_path() {
+        let graph = two_ways();
+        let path = graph.shortest_path((9, 0)).unwrap();
+        assert_eq!(path[1..], [(1, 0), (4, 0), (9, 0)]);
+        assert_eq!(path[0], graph.arena().get(graph.root()).key());
+        assert_eq!(graph.shortest_path((7, 0)), None);
+    }
+
+    #[test]
+    fn test_heaviest_path() {
+        let graph = two_ways();
+        let path = graph.heaviest_path((9, 0)).unwrap();
+        assert_eq!(path[1..], [(1, 0), (2, 0), (3, 0), (9, 0)]);
+        assert_eq!(graph.heaviest_path((1, 0)).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_root_path() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0)]);
+        let root = graph.root();
+        assert_eq!(shortest_path(&graph, root).unwrap().len(), 1);
+        assert_eq!(heaviest_path(&graph, root).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,116 @@
+use nodes::{Arena, ENode, GraphView, NodeId};
+use partition::{PartitionPlan, PartitionStrategy};
+
+/// Weight of a node that was never hit; a node hit `n` times weighs
+/// `RARITY_SCALE / (n + 1)`, but at least 1.
+pub const RARITY_SCALE: usize = 1024;
+
+/// Rarity weight of a node. Unclaimed placeholders, i.e. children nobody
+/// has reached yet, count as never hit.
+pub fn rarity_weight(node: &ENode) -> usize {
+    let hits = if node._claimed { node.weight } else { 0 };
+    (RARITY_SCALE / (hits + 1)).max(1)
+}
+
+/// Copy of a graph whose node weights are rarity weights, so partitioning it
+/// balances the rarely hit parts of the program instead of the hot ones.
+/// Keys and edges are those of the original graph.
+pub struct RarityView {
+    arena: Arena,
+    root: NodeId,
+}
+
+impl RarityView {
+    pub fn new(graph: &dyn GraphView) -> Self {
+        let mut arena = Arena::new();
+        for (_, node) in graph.arena().iter() {
+            let mut copy = node.clone();
+            copy.weight = rarity_weight(node);
+            arena.alloc(copy);
+        }
+        RarityView {
+            arena,
+            root: graph.root(),
+        }
+    }
+}
+
+impl GraphView for RarityView {
+    fn root(&self) -> NodeId {
+        self.root
+    }
+
+    fn arena(&self) -> &Arena {
+        &self.arena
+    }
+}
+
+/// Runs another strategy on the `RarityView` of the graph.
+pub struct RarityWeighted(pub Box<dyn PartitionStrategy>);
+
+impl PartitionStrategy for RarityWeighted {
+    fn partition(&self, graph: &dyn GraphView, k: usize) -> Vec<PartitionPlan> {
+        self.0.partition(&RarityView::new(graph), k)
+    }
+
+    fn units(&self, graph: &dyn GraphView, k: usize) -> Vec<Option<usize>> {
+        self.0.units(&RarityView::new(graph), k)
+    }
+
+    fn groups(&self, k: usize) -> Vec<usize> {
+        self.0.groups(k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dgraph::DGraph;
+    use egraph::ETree;
+    use partition::Multilevel;
+
+    #[test]
+    fn test_rarity_weight() {
+        let mut node = ENode::new_claimed(1, 0);
+        node.weight = 0;
+        assert_eq!(rarity_weight(&node), RARITY_SCALE);
+        node.weight = 3;
+        assert_eq!(rarity_weight(&node), RARITY_SCALE / 4);
+        node.weight = 1 << 20;
+        assert_eq!(rarity_weight(&node), 1);
+        assert_eq!(rarity_weight(&ENode::new_unclaimed(5)), RARITY_SCALE);
+    }
+
+    #[test]
+    fn test_rare_nodes_spread() {
+        // a hot chain and a handful of rare leaves hanging off it
+        let mut graph = DGraph::new();
+        for _ in 0..200 {
+            graph.add_trace(vec![(1, 0), (2, 0), (3, 0), (4, 0)]);
+        }
+        for leaf in 10..14 {
+            graph.add_trace(vec![(1, 0), (leaf, 0)]);
+        }
+
+        let plans = RarityWeighted(Box::new(Multilevel)).partition(&graph, 2);
+        let rare = |plan: &PartitionPlan| plan.plan.iter().filter(|(idx, _)| *idx >= 10).count();
+        assert_eq!(rare(&plans[0]) + rare(&plans[1]), 4);
+        assert!(rare(&plans[0]) >= 1 && rare(&plans[1]) >= 1);
+        // plan weights are rarity weights
+        let total: usize = plans.iter().map(|p| p.weight).sum();
+        assert_eq!(total, RarityView::new(&graph).arena().iter().map(|(_, n)| n.weight).sum::<usize>());
+    }
+
+    #[test]
+    fn test_placeholders_weigh() {
+        let mut tree = ETree::new(ENode::new_unclaimed(1));
+        tree.add_trace(vec![(2, 0)]);
+        let view = RarityView::new(&tree);
+        let placeholders: Vec<usize> = view.arena().iter()
+            .filter(|(_, node)| !node._claimed)
+            .map(|(_, node)| node.weight)
+            .collect();
+        assert!(!placeholders.is_empty());
+        assert!(placeholders.iter().all(|w| *w == RARITY_SCALE));
+    }
+}
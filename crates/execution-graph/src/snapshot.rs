@@ -0,0 +1,109 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use assignment::PartitionAssignment;
+use dgraph::DGraph;
+use packets::invalid_data;
+use partition::PartitionPlan;
+
+/// Bumped whenever the layout of `SerializedSnapshot` changes, including
+/// the embedded graph's (`dgraph::DGRAPH_FORMAT_VERSION`).
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 2;
+
+/// What a coordinator needs to resume a campaign: the merged graph and the
+/// ownership it handed out.
+pub struct Snapshot {
+    pub graph: DGraph,
+    pub assignment: PartitionAssignment,
+    pub plans: Vec<PartitionPlan>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializedSnapshot {
+    version: u32,
+    // `DGraph::serialize` output
+    graph: Vec<u8>,
+    assignment: PartitionAssignment,
+    plans: Vec<PartitionPlan>,
+}
+
+/// Writes a snapshot to `path`. The file is replaced atomically, so a crash
+/// while saving leaves the previous snapshot intact.
+pub fn save(path: &Path, graph: &DGraph, assignment: &PartitionAssignment,
+            plans: &[PartitionPlan]) -> io::Result<()> {
+    let bytes = bincode::serialize(&SerializedSnapshot {
+        version: SNAPSHOT_FORMAT_VERSION,
+        graph: graph.serialize(),
+        assignment: assignment.clone(),
+        plans: plans.to_vec(),
+    }).map_err(invalid_data)?;
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, path)
+}
+
+pub fn load(path: &Path) -> io::Result<Snapshot> {
+    let bytes = fs::read(path)?;
+    let version: u32 = bincode::deserialize(&bytes).map_err(invalid_data)?;
+    if version != SNAPSHOT_FORMAT_VERSION {
+        return Err(invalid_data(format!("unsupported snapshot format version {}", version)));
+    }
+    let snapshot: SerializedSnapshot = bincode::deserialize(&bytes).map_err(invalid_data)?;
+    Ok(Snapshot {
+        graph: DGraph::try_deserialize(snapshot.graph)?,
+        assignment: snapshot.assignment,
+        plans: snapshot.plans,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_save_load() {
+        let mut graph = DGraph::new();
+        graph.add_trace(vec![(1, 0), (2, 0), (1, 1)]);
+        graph.add_trace(vec![(1, 0), (3, 0)]);
+        let plans = graph.partition(2);
+        let assignment = PartitionAssignment::from_plans(7, &plans, &[1, 2]);
+
+        let path = env::temp_dir().join(format!("dgraph-snapshot-{}", std::process::id()));
+        save(&path, &graph, &assignment, &plans).unwrap();
+        let snapshot = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(snapshot.graph == graph);
+        assert_eq!(snapshot.assignment, assignment);
+        assert_eq!(snapshot.plans.len(), 2);
+        assert_eq!(snapshot.plans[0].plan, plans[0].plan);
+    }
+
+    #[test]
+    fn test_load_rejects_garbage() {
+        let path = env::temp_dir().join(format!("dgraph-garbage-{}", std::process::id()));
+        fs::write(&path, [9, 0, 0, 0, 1]).unwrap();
+        let error = load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_load_rejects_old_graph() {
+        let path = env::temp_dir().join(format!("dgraph-old-graph-{}", std::process::id()));
+        let bytes = bincode::serialize(&SerializedSnapshot {
+            version: SNAPSHOT_FORMAT_VERSION,
+            // a graph of format version 1
+            graph: bincode::serialize(&1u32).unwrap(),
+            assignment: PartitionAssignment::from_plans(1, &[], &[]),
+            plans: vec![],
+        }).unwrap();
+        fs::write(&path, bytes).unwrap();
+        let error = load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
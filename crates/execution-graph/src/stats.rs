@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::fmt;
+use nodes::GraphView;
+use partition::{self, PartitionPlan};
+
+/// Shape of a graph, cheap enough to compute every epoch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GraphStats {
+    pub nodes: usize,
+    pub edges: usize,
+    // longest of the shortest paths from the root, in edges
+    pub max_depth: usize,
+    // average number of children of nodes that have any
+    pub branching_factor: f64,
+    // bucket 0 counts weight 0, bucket i weights in [2^(i-1), 2^i)
+    pub weight_histogram: Vec<usize>,
+}
+
+/// How well a set of plans splits a graph. A node listed in several plans
+/// counts for the first of them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartitionQuality {
+    pub part_nodes: Vec<usize>,
+    pub part_weights: Vec<usize>,
+    // heaviest part over the average part, 1.0 is perfectly balanced
+    pub imbalance: f64,
+    // edges whose endpoints are owned by different plans
+    pub cut_edges: usize,
+    // nodes no plan lists
+    pub unowned: usize,
+}
+
+fn weight_bucket(weight: usize) -> usize {
+    (usize::BITS - weight.leading_zeros()) as usize
+}
+
+pub fn graph_stats(graph: &dyn GraphView) -> GraphStats {
+    let arena = graph.arena();
+    let mut edges = 0;
+    let mut inner = 0;
+    let mut weight_histogram = vec![];
+    for (_, node) in arena.iter() {
+        edges += node.children.len();
+        if !node.children.is_empty() {
+            inner += 1;
+        }
+        let bucket = weight_bucket(node.weight);
+        if weight_histogram.len() <= bucket {
+            weight_histogram.resize(bucket + 1, 0);
+        }
+        weight_histogram[bucket] += 1;
+    }
+
+    let mut depth = vec![None; arena.len()];
+    let mut max_depth = 0;
+    let mut queue = VecDeque::new();
+    if !arena.is_empty() {
+        depth[graph.root()] = Some(0);
+        queue.push_back(graph.root());
+    }
+    while let Some(id) = queue.pop_front() {
+        let d = depth[id].unwrap();
+        max_depth = max_depth.max(d);
+        for child in &arena.get(id).children {
+            if depth[*child].is_none() {
+                depth[*child] = Some(d + 1);
+                queue.push_back(*child);
+            }
+        }
+    }
+
+    GraphStats {
+        nodes: arena.len(),
+        edges,
+        max_depth,
+        branching_factor: if inner == 0 { 0.0 } else { edges as f64 / inner as f64 },
+        weight_histogram,
+    }
+}
+
+pub fn partition_quality(graph: &dyn GraphView, plans: &[PartitionPlan]) -> PartitionQuality {
+    let owner = partition::owners(plans);
+
+    let arena = graph.arena();
+    let owners: Vec<Option<usize>> = arena.iter().map(|(_, node)| owner.get(&node.key()).copied()).collect();
+    let mut part_nodes = vec![0; plans.len()];
+    let mut part_weights = vec![0; plans.len()];
+    let mut unowned = 0;
+    let mut cut_edges = 0;
+    for (id, node) in arena.iter() {
+        match owners[id] {
+            Some(part) => {
+                part_nodes[part] += 1;
+                part_weights[part] += node.weight;
+            }
+            None => unowned += 1,
+        }
+        for child in &node.children {
+            if let (Some(a), Some(b)) = (owners[id], owners[*child]) {
+                if a != b {
+                    cut_edges += 1;
+                }
+            }
+        }
+    }
+
+    let total: usize = part_weights.iter().sum();
+    let heaviest = part_weights.iter().copied().max().unwrap_or(0);
+    let imbalance = if total == 0 {
+        1.0
+    } else {
+        heaviest as f64 * plans.len() as f64 / total as f64
+    };
+    PartitionQuality {
+        part_nodes,
+        part_weights,
+        imbalance,
+        cut_edges,
+        unowned,
+    }
+}
+
+impl fmt::Display for GraphStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} nodes, {} edges, depth {}, branching {:.2}, weights {:?}",
+               self.nodes, self.edges, self.max_depth, self.branching_factor, self.weight_histogram)
+    }
+}
+
+impl fmt::Display for PartitionQuality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "imbalance {:.2}, {} cut edges, nodes {:?}, weights {:?}, {} unowned",
+               self.imbalance, self.cut_edges, self.part_nodes, self.part_weights, self.unowned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixtures::sample;
+    use partition::{CountChunking, PartitionStrategy};
+
+    #[test]
+    fn test_graph_stats() {
+        let stats = graph_stats(&sample());
+        assert_eq!(stats.nodes, 6);
+        assert_eq!(stats.edges, 5);
+        assert_eq!(stats.max_depth, 3);
+        // root -> 1, 1 -> {2, 3}, 3 -> {4, 5}
+        assert_eq!(stats.branching_factor, 5.0 / 3.0);
+        // (1, 0) has weight 3, (3, 0) weight 2, everything else weight 1
+        assert_eq!(stats.weight_histogram, vec![0, 4, 2]);
+    }
+
+    #[test]
+    fn test_partition_quality() {
+        let graph = sample();
+        let plans = CountChunking.partition(&graph, 2);
+        let quality = partition_quality(&graph, &plans);
+        assert_eq!(quality.part_nodes, vec![3, 3]);
+        assert_eq!(quality.part_nodes.iter().sum::<usize>() + quality.unowned, 6);
+        assert_eq!(quality.part_weights.iter().sum::<usize>(), 9);
+        assert!(quality.imbalance >= 1.0);
+        assert!(quality.cut_edges > 0);
+    }
+
+    #[test]
+    fn test_partition_quality_no_plans() {
+        let quality = partition_quality(&sample(), &[]);
+        assert_eq!(quality.unowned, 6);
+        assert_eq!(quality.cut_edges, 0);
+        assert_eq!(quality.imbalance, 1.0);
+    }
+}
@@ -25,38 +25,37 @@ use libafl::feedbacks::{DifferentIsNovel, IsNovel, MapFeedbackMetadata, MapIndex
 use libafl::inputs::Input;
 use libafl::prelude::HasBytesVec;
 use p2p::P2P;
-use execution_graph::dgraph::DGraph;
 pub static mut IGNORED: [bool; 4096] = [false; 4096];
 
 /// The prefix of the metadata names
 pub const DMapFeedback_PREFIX: &str = "DMapFeedback_metadata_";
 
-pub struct DMapFeedback<'a, N, O, R, S, T, I> {
+pub struct DMapFeedback<'a, N, O, R, S, T, I, G> {
     always_track: bool,
     indexes: bool,
     observer_name: String,
     on_testcase_found: fn(&[u8], &[usize], &P2P),
-    on_execution_finished: fn(&P2P,  Rc<RefCell<DGraph>>),
+    on_execution_finished: fn(&P2P,  Rc<RefCell<G>>),
     name: String,
     pub(crate) ignored: Vec<bool>,
     p2p: &'a P2P,
-    graph: Rc<RefCell<DGraph>>,
+    graph: Rc<RefCell<G>>,
     phantom: PhantomData<(N, O, R, S, T, I)>,
 }
 
-impl<'a, N, O, R, S, T, I> Clone for DMapFeedback<'a, N, O, R, S, T, I> {
+impl<'a, N, O, R, S, T, I, G> Clone for DMapFeedback<'a, N, O, R, S, T, I, G> {
     fn clone(&self) -> Self {
         todo!()
     }
 }
 
-impl<'a, N, O, R, S, T, I>  Debug for DMapFeedback<'a, N, O, R, S, T, I>  {
+impl<'a, N, O, R, S, T, I, G>  Debug for DMapFeedback<'a, N, O, R, S, T, I, G>  {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         todo!()
     }
 }
 
-impl<'a, N, O, R, S, T, I> UsesObserver<S> for DMapFeedback<'a, N, O, R, S, T, I>
+impl<'a, N, O, R, S, T, I, G> UsesObserver<S> for DMapFeedback<'a, N, O, R, S, T, I, G>
     where
         S: UsesInput,
         O: Observer<S>,
@@ -65,7 +64,7 @@ impl<'a, N, O, R, S, T, I> UsesObserver<S> for DMapFeedback<'a, N, O, R, S, T, I
 }
 
 /// Specialize for the common coverage map size, maximization of u8s
-impl<'a, N, O, R, S, T, I> Feedback<S> for DMapFeedback<'a, N, O, R, S, T, I>
+impl<'a, N, O, R, S, T, I, G> Feedback<S> for DMapFeedback<'a, N, O, R, S, T, I, G>
     where
         T: PartialEq + Default + Copy + 'static + Serialize + DeserializeOwned + Debug,
         R: Reducer<T>,
@@ -146,14 +145,14 @@ impl<'a, N, O, R, S, T, I> Feedback<S> for DMapFeedback<'a, N, O, R, S, T, I>
     }
 }
 
-impl<'a, N, O, R, S, T, I> Named for DMapFeedback<'a, N, O, R, S, T, I> {
+impl<'a, N, O, R, S, T, I, G> Named for DMapFeedback<'a, N, O, R, S, T, I, G> {
     #[inline]
     fn name(&self) -> &str {
         self.name.as_str()
     }
 }
 
-impl<'a, N, O, R, S, T, I> HasObserverName for DMapFeedback<'a, N, O, R, S, T, I>
+impl<'a, N, O, R, S, T, I, G> HasObserverName for DMapFeedback<'a, N, O, R, S, T, I, G>
     where
         T: PartialEq + Default + Copy + 'static + Serialize + DeserializeOwned + Debug,
         R: Reducer<T>,
@@ -172,7 +171,7 @@ fn create_stats_name(name: &str) -> String {
     name.to_lowercase()
 }
 
-impl<'a, N, O, R, S, T, I> DMapFeedback<'a, N, O, R, S, T, I>
+impl<'a, N, O, R, S, T, I, G> DMapFeedback<'a, N, O, R, S, T, I, G>
     where
         T: PartialEq + Default + Copy + 'static + Serialize + DeserializeOwned + Debug,
         R: Reducer<T>,
@@ -183,8 +182,8 @@ impl<'a, N, O, R, S, T, I> DMapFeedback<'a, N, O, R, S, T, I>
 {
     pub fn tracking(map_observer: &O, track_indexes: bool,
                     p2p: &'a P2P,
-                    graph: Rc<RefCell<DGraph>>,
-                    on_testcase_found: fn (&[u8], &[usize], &P2P), on_execution_finished: fn(&P2P,  Rc<RefCell<DGraph>>)) -> Self {
+                    graph: Rc<RefCell<G>>,
+                    on_testcase_found: fn (&[u8], &[usize], &P2P), on_execution_finished: fn(&P2P,  Rc<RefCell<G>>)) -> Self {
         Self {
             indexes: track_indexes,
             name: DMapFeedback_PREFIX.to_string() + map_observer.name(),
@@ -22,15 +22,16 @@ use serde::de::DeserializeOwned;
 use libafl_targets::{libfuzzer_initialize, libfuzzer_test_one_input, EDGES_MAP, MAX_EDGES_NUM};
 use feedback::DMapFeedback;
 use p2p::P2P;
-use execution_graph::dgraph::DGraph;
 
 
-pub fn fuzz_process_epoch(
+/// Runs the fuzzing loop; `G` is the execution model handed to the callbacks
+/// (e.g. `DGraph` or `ETree` from `execution_graph`).
+pub fn fuzz_process_epoch<G>(
     p2p: &P2P,
-    dgraph: Rc<RefCell<DGraph>>,
+    dgraph: Rc<RefCell<G>>,
     on_testcase_found: fn (&[u8], &[usize], &P2P),
-    on_execution_finished: fn(&P2P, Rc<RefCell<DGraph>>),
-    sync_corpus: fn(p2p: &P2P, Rc<RefCell<DGraph>>) -> Vec<Vec<u8>>,
+    on_execution_finished: fn(&P2P, Rc<RefCell<G>>),
+    sync_corpus: fn(p2p: &P2P, Rc<RefCell<G>>) -> Vec<Vec<u8>>,
     ignored: Vec<bool>,
 ) {
     let edges_observer = unsafe {
@@ -43,7 +44,7 @@ pub fn fuzz_process_epoch(
 
     let time_observer = TimeObserver::new("time");
     let mut map_feedback = DMapFeedback
-        ::<DifferentIsNovel, _, MaxReducer, _, _, _>
+        ::<DifferentIsNovel, _, MaxReducer, _, _, _, _>
     ::tracking(&edges_observer, true, p2p, dgraph.clone(), on_testcase_found, on_execution_finished);
 
     map_feedback.ignored = ignored;
@@ -1,29 +1,48 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::ops::Deref;
+use std::ffi::c_void;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
 use std::rc::Rc;
+use std::sync::Mutex;
 use std::thread::sleep;
+use std::time::{Duration, Instant};
 use fuzzer::p2p::P2P;
 use mpi;
 use mpi::topology::Communicator;
+use mpi::collective::CommunicatorCollectives;
 use mpi::point_to_point::{Destination, ReceiveFuture, Source, Status};
 use mpi::Rank;
 use fuzzer::fuzzing::fuzz_process_epoch;
 use lazy_static::lazy_static;
 use mpi::traits::Equivalence;
-use execution_graph::db;
 // 1.4.0
-use execution_graph::dgraph::DGraph;
-use execution_graph::partition::PartitionPlan;
+use execution_graph::dgraph::{DGraph, WeightDecay};
+use execution_graph::delta::{DGraphDelta, DeltaAssembler};
+use execution_graph::egraph::ETree;
+use execution_graph::nodes::{GraphView, NodeKey};
+use execution_graph::packets::{self, PacketAssembler};
+use execution_graph::assignment::{AssignmentAssembler, PartitionAssignment};
+use execution_graph::rarity::RarityView;
+use execution_graph::partition::{self, strategy_from_name, CodeUnits, Hierarchical, PartitionPlan, PartitionStrategy};
+use execution_graph::compress::TraceCompressor;
+use execution_graph::context::{context_idx, edge_of, enter_call};
+use execution_graph::codemap::{CodeMap, Granularity, PcTableEntry, Symbol};
+use execution_graph::export;
+use execution_graph::snapshot;
+use execution_graph::stats::{graph_stats, partition_quality};
+use execution_graph::dominator::DominatorTree;
 use fuzzer::feedback::IGNORED;
 
 /// Msg: 0..4 -> Pkt Len (big endian)
 /// Msg: 4 -> Pkt Type
 /// Msg: 5.. -> Pkt Data
 ///
-/// 0 -> Share execution tree
-/// 1 -> Share latest execution tree
+/// 0 -> Share execution tree changes since the last sync
+/// 1 -> Share latest execution tree changes from the coordinator
 /// 2 -> Share spills
+/// 3 -> Share one packet of the coordinator's partition assignment
+/// 4 -> Share one packet of the execution paths taken since the last sync
 #[derive(Equivalence)]
 struct Wrapper {
     buf: [u8; 4096],
@@ -32,20 +51,98 @@ struct Wrapper {
 
 pub static mut __extern_ptrace: [u32; 4096] = [0; 4096];
 
+// calling-context hash of every __extern_ptrace entry, see __extern_trace_context
+#[no_mangle]
+pub static mut __extern_ctxtrace: [u64; 4096] = [0; 4096];
+
+// calling-context hash of the running code, kept by the -finstrument-functions
+// hooks below
+#[no_mangle]
+pub static mut __extern_context: u64 = 0;
+
+// contexts of the callers, restored when their callees return; deeper calls
+// than this keep the context of the deepest one recorded
+const SHADOW_STACK_DEPTH: usize = 1024;
+static mut SHADOW_STACK: [u64; SHADOW_STACK_DEPTH] = [0; SHADOW_STACK_DEPTH];
+static mut SHADOW_DEPTH: usize = 0;
+
 // who owns what
 pub static mut __partitions: [u32; 4096] = [0; 4096];
 
+// generation the next execution tree delta starts from
+static mut DELTA_SINCE: u64 = 0;
+
+// how far above average a partition may grow before nodes migrate
+const REPARTITION_IMBALANCE_PCT: usize = 20;
+// bottlenecks reported after every repartition
+const TOP_BOTTLENECKS: usize = 5;
+
+lazy_static! {
+    // partition assignment packets received so far
+    static ref ASSIGNMENT: Mutex<AssignmentAssembler> = Mutex::new(AssignmentAssembler::new());
+    // packets of the coordinator's execution tree delta received so far
+    static ref DELTA: Mutex<DeltaAssembler> = Mutex::new(DeltaAssembler::new());
+    // the assignment applied last, to find owners by edge and calling context
+    static ref CONTEXT_OWNERS: Mutex<PartitionAssignment> = Mutex::new(PartitionAssignment::default());
+    // turns __extern_ptrace into execution tree keys, loops collapsed
+    static ref COMPRESSOR: Mutex<TraceCompressor> = Mutex::new(TraceCompressor::default());
+    // sancov PC tables of all instrumented modules, in edge index order
+    static ref PC_TABLE: Mutex<Vec<PcTableEntry>> = Mutex::new(vec![]);
+    // EXECUTION_GRAPH=etree partitions the tree of execution paths instead of the edge graph
+    static ref PATH_TREE: bool = match std::env::var("EXECUTION_GRAPH").as_deref() {
+        Ok("etree") => true,
+        Ok("dgraph") | Err(_) => false,
+        Ok(other) => panic!("unknown EXECUTION_GRAPH {}", other),
+    };
+    // PARTITION_STRATEGY=chunk|leaf|multilevel|dominator|hierarchical|function|file
+    static ref PARTITION_STRATEGY: String = std::env::var("PARTITION_STRATEGY").unwrap_or("multilevel".to_string());
+    // chosen with WEIGHT_DECAY=none|exp:<percent>|window:<epochs>
+    static ref WEIGHT_DECAY: WeightDecay = {
+        let spec = std::env::var("WEIGHT_DECAY").unwrap_or("none".to_string());
+        WeightDecay::from_spec(&spec).expect("invalid WEIGHT_DECAY")
+    };
+    // DGRAPH_NODE_BUDGET=<nodes> bounds the execution tree on every rank
+    static ref NODE_BUDGET: Option<usize> = std::env::var("DGRAPH_NODE_BUDGET").ok()
+        .map(|budget| budget.parse().expect("invalid DGRAPH_NODE_BUDGET"));
+    // SNAPSHOT_PATH=<file> saves the coordinator's graph and assignment every epoch
+    static ref SNAPSHOT_PATH: Option<String> = std::env::var("SNAPSHOT_PATH").ok();
+    // RESUME_SNAPSHOT=<file> makes the coordinator start from a saved snapshot
+    static ref RESUME_SNAPSHOT: Option<String> = std::env::var("RESUME_SNAPSHOT").ok();
+    // PARTITION_WEIGHTING=rarity balances rarely hit nodes instead of hit counts
+    static ref RARITY_WEIGHTING: bool = match std::env::var("PARTITION_WEIGHTING").as_deref() {
+        Ok("rarity") => true,
+        Ok("hits") | Err(_) => false,
+        Ok(other) => panic!("unknown PARTITION_WEIGHTING {}", other),
+    };
+    // WORKER_TIMEOUT_SECS=<secs> a worker may stay silent before its partition is handed out
+    static ref WORKER_TIMEOUT: Duration = Duration::from_secs(std::env::var("WORKER_TIMEOUT_SECS").ok()
+        .map_or(300, |secs| secs.parse().expect("invalid WORKER_TIMEOUT_SECS")));
+    // CONTEXT_SENSITIVE=1 keys execution tree nodes by edge and calling context
+    static ref CONTEXT_SENSITIVE: bool = std::env::var("CONTEXT_SENSITIVE").map_or(false, |v| v == "1");
+    // with GRAPH_DUMP_DIR set, every new assignment is dumped there as DOT and JSON
+    static ref GRAPH_DUMP_DIR: Option<String> = std::env::var("GRAPH_DUMP_DIR").ok();
+}
+
 fn on_testcase_found(data: &[u8], intt: &[usize], p2p: &P2P) {
     for i in intt {
         let mut last: usize = 0;
+        let mut last_pos: usize = 0;
         // find parents of interesting hits
-        for t in unsafe {__extern_ptrace.iter()} {
+        for (pos, t) in unsafe {__extern_ptrace.iter()}.enumerate() {
             if *t as usize == *i {
                 break;
             }
             last = *t as usize;
+            last_pos = pos;
         }
-        let owner_rank = unsafe { __partitions[last as usize] };
+        // with calling contexts, the owner of the parent in its own context
+        let contextual = if *CONTEXT_SENSITIVE {
+            context_idx(last as u32, unsafe { __extern_ctxtrace[last_pos] }).ok()
+                .and_then(|idx| CONTEXT_OWNERS.lock().unwrap().owner_of(idx))
+        } else {
+            None
+        };
+        let owner_rank = contextual.unwrap_or_else(|| unsafe { __partitions[last as usize] });
         if owner_rank != 0 {
             let mut msg = vec![0; data.len() + 5];
             let size = data.len() + 1;
@@ -62,17 +159,170 @@ fn on_testcase_found(data: &[u8], intt: &[usize], p2p: &P2P) {
     }
 }
 
+// keys of the execution that just finished, see `ExecutionGraph::add_trace`
+fn execution_keys() -> Vec<NodeKey> {
+    if *CONTEXT_SENSITIVE {
+        let indices: Result<Vec<u32>, _> = unsafe { __extern_ptrace.iter().zip(__extern_ctxtrace.iter()) }
+            .map(|(edge, context)| context_idx(*edge, *context))
+            .collect();
+        match indices {
+            Ok(indices) => return COMPRESSOR.lock().unwrap().compress(&indices),
+            Err(e) => println!("{}, keeping this execution without calling contexts", e),
+        }
+    }
+    COMPRESSOR.lock().unwrap().compress(unsafe { &__extern_ptrace })
+}
+
 fn on_execution_finished(p2p: &P2P, dgraph: Rc<RefCell<DGraph>>) {
     // inside partition!
-    let mut appearance = vec![0; 4096];
-    let mut trace = vec![];
-    for i in unsafe { __extern_ptrace.iter() } {
-        trace.push((*i as u32, appearance[*i as usize]));
-        appearance[*i as usize] = (appearance[*i as usize] + 1) % 255;
+    dgraph.borrow_mut().add_trace(execution_keys())
+}
+
+// with EXECUTION_GRAPH=etree, workers only collect the paths taken since the last sync
+fn on_execution_finished_tree(p2p: &P2P, paths: Rc<RefCell<ETree>>) {
+    paths.borrow_mut().add_trace(execution_keys())
+}
+
+
+// called by -finstrument-functions instrumentation on every call
+#[no_mangle]
+pub unsafe extern "C" fn __cyg_profile_func_enter(_func: *const c_void, call_site: *const c_void) {
+    if SHADOW_DEPTH < SHADOW_STACK_DEPTH {
+        SHADOW_STACK[SHADOW_DEPTH] = __extern_context;
+        __extern_context = enter_call(__extern_context, call_site as u64);
+    }
+    SHADOW_DEPTH += 1;
+}
+
+// called by -finstrument-functions instrumentation on every return
+#[no_mangle]
+pub unsafe extern "C" fn __cyg_profile_func_exit(_func: *const c_void, _call_site: *const c_void) {
+    SHADOW_DEPTH = SHADOW_DEPTH.saturating_sub(1);
+    if SHADOW_DEPTH < SHADOW_STACK_DEPTH {
+        __extern_context = SHADOW_STACK[SHADOW_DEPTH];
+    }
+}
+
+// called by the edge instrumentation after it stored __extern_ptrace[pos]
+#[no_mangle]
+pub unsafe extern "C" fn __extern_trace_context(pos: u32) {
+    if let Some(context) = __extern_ctxtrace.get_mut(pos as usize) {
+        *context = __extern_context;
+    }
+}
+
+// called by -fsanitize-coverage=pc-table instrumentation for every module
+#[no_mangle]
+pub unsafe extern "C" fn __sanitizer_cov_pcs_init(beg: *const PcTableEntry, end: *const PcTableEntry) {
+    let len = end.offset_from(beg) as usize;
+    PC_TABLE.lock().unwrap().extend_from_slice(std::slice::from_raw_parts(beg, len));
+}
+
+// llvm-symbolizer (or LLVM_SYMBOLIZER) running on our own executable
+struct Symbolizer {
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    // where the executable is loaded, PCs are looked up relative to it
+    base: usize,
+}
+
+impl Symbolizer {
+    fn new() -> std::io::Result<Self> {
+        let exe = std::env::current_exe()?;
+        let maps = std::fs::read_to_string("/proc/self/maps")?;
+        let base = maps.lines()
+            .find(|line| line.ends_with(exe.to_str().unwrap_or("")))
+            .and_then(|line| line.split('-').next())
+            .and_then(|start| usize::from_str_radix(start, 16).ok())
+            .unwrap_or(0);
+        let program = std::env::var("LLVM_SYMBOLIZER").unwrap_or("llvm-symbolizer".to_string());
+        let mut child = Command::new(program)
+            .arg(format!("--obj={}", exe.display()))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(Symbolizer { _child: child, stdin, stdout, base })
+    }
+
+    // answers are "function\nfile:line:column\n\n"
+    fn symbolize(&mut self, pc: usize) -> Symbol {
+        writeln!(self.stdin, "0x{:x}", pc.wrapping_sub(self.base)).unwrap();
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            if self.stdout.read_line(&mut line).unwrap() == 0 || line.trim().is_empty() {
+                break;
+            }
+            lines.push(line.trim().to_string());
+        }
+        Symbol {
+            function: lines.first().cloned().unwrap_or_default(),
+            file: lines.get(1).and_then(|l| l.split(':').next()).unwrap_or("").to_string(),
+        }
     }
-    db!(mut, dgraph).add_trace(trace)
 }
 
+fn code_map() -> CodeMap {
+    let table = PC_TABLE.lock().unwrap();
+    assert!(!table.is_empty(), "no sancov PC table, build the target with -fsanitize-coverage=pc-table");
+    let mut symbolizer = Symbolizer::new().expect("cannot start llvm-symbolizer");
+    CodeMap::from_pc_table(&table, |pc| symbolizer.symbolize(pc))
+}
+
+// longest MPI processor name we tell apart
+const MAX_HOST_NAME: usize = 256;
+
+// every rank's MPI processor name, indexed by rank; collective
+fn host_names(p2p: &P2P) -> Vec<String> {
+    let name = mpi::environment::processor_name().unwrap();
+    let mut buf = [0u8; MAX_HOST_NAME];
+    let len = name.len().min(MAX_HOST_NAME);
+    buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+    let mut all = vec![0u8; MAX_HOST_NAME * p2p.world.size() as usize];
+    p2p.world.all_gather_into(&buf[..], &mut all[..]);
+    all.chunks(MAX_HOST_NAME)
+        .map(|n| String::from_utf8_lossy(n).trim_end_matches('\0').to_string())
+        .collect()
+}
+
+fn make_packet(pkt_type: u8, data: &[u8]) -> Vec<u8> {
+    let size = data.len() + 1;
+    let mut msg = vec![0; 5];
+    msg[0] = (size >> 24) as u8;
+    msg[1] = (size >> 16) as u8;
+    msg[2] = (size >> 8) as u8;
+    msg[3] = size as u8;
+    msg[4] = pkt_type;
+    msg.extend_from_slice(data);
+    msg
+}
+
+// swaps in a complete assignment: we ignore coverage owned by other ranks
+// and forward to the owners recorded in __partitions
+fn apply_assignment(assignment: &PartitionAssignment, rank: u32) {
+    let owners = assignment.owner_by_index(4096);
+    let mut ignored = [false; 4096];
+    for (idx, owner) in owners.iter().enumerate() {
+        ignored[idx] = *owner != 0 && *owner != rank;
+    }
+    if *CONTEXT_SENSITIVE {
+        // an edge is ours to watch while any of its callers is ours
+        for ((idx, _), owner) in &assignment.owners {
+            if *owner == rank && (edge_of(*idx) as usize) < ignored.len() {
+                ignored[edge_of(*idx) as usize] = false;
+            }
+        }
+        *CONTEXT_OWNERS.lock().unwrap() = assignment.clone();
+    }
+    unsafe {
+        __partitions.copy_from_slice(&owners);
+        IGNORED.copy_from_slice(&ignored);
+    }
+    println!("Applied partition assignment of epoch {}", assignment.epoch);
+}
 
 pub fn get_u32(v: &[u8], offset: usize) -> u32 {
     let mut size = 0;
@@ -84,23 +334,32 @@ pub fn get_u32(v: &[u8], offset: usize) -> u32 {
 }
 
 fn sync_corpus(p2p: &P2P, dgraph: Rc<RefCell<DGraph>>) -> Vec<Vec<u8>> {
-    // send execution tree
-    let mut msg = vec![0; 4096];
-    let mut serialized = db!(dgraph).serialize();
-    let size = serialized.len() + 1;
-    assert!(size < 4091);
-
-    msg[0] = (size >> 24) as u8;
-    msg[1] = (size >> 16) as u8;
-    msg[2] = (size >> 8) as u8;
-    msg[3] = size as u8;
-    msg[4] = 0;
-    msg[5..].copy_from_slice(&serialized[..]);
+    // send execution tree changes, minus what we learned from the coordinator
+    let delta = dgraph.borrow_mut().delta_for(unsafe { DELTA_SINCE }, 0);
+    unsafe { DELTA_SINCE = delta.to; }
+    dgraph.borrow_mut().compact_history(delta.to);
+    for packet in delta.to_packets(4096 - 5) {
+        p2p.send(make_packet(0, &packet), 0);
+    }
 
-    p2p.send(msg, 0);
+    receive_packets(p2p, |delta| dgraph.borrow_mut().apply_delta(&delta, 0))
+}
 
+fn sync_corpus_tree(p2p: &P2P, paths: Rc<RefCell<ETree>>) -> Vec<Vec<u8>> {
+    // hand the paths taken since the last sync to the coordinator and start over
+    let paths = paths.replace(ETree::default());
+    let id = unsafe {
+        DELTA_SINCE += 1;
+        DELTA_SINCE
+    };
+    for packet in packets::to_packets(&paths.serialize(), id, 4096 - 5) {
+        p2p.send(make_packet(4, &packet), 0);
+    }
+    receive_packets(p2p, |_| {})
+}
 
-    // share with others
+// handles everything the coordinator and other workers sent; returns the testcases
+fn receive_packets<F: FnMut(DGraphDelta)>(p2p: &P2P, mut on_delta: F) -> Vec<Vec<u8>> {
     let mut result = vec![];
 
     loop {
@@ -111,24 +370,21 @@ fn sync_corpus(p2p: &P2P, dgraph: Rc<RefCell<DGraph>>) -> Vec<Vec<u8>> {
                 let pkt_type = msg[4];
 
                 if pkt_type == 1 {
-                    let tree_size = get_u32(&msg, 0) - 1;
-                    let tree = &msg[5..(5 + tree_size as usize)];
-                    let lg = DGraph::deserialize(tree.to_vec());
-                    db!(mut, dgraph).merge(&lg);
-                    let pps = db!(mut, dgraph).partition(p2p.world.size() as usize);
-                    let ignored_p: PartitionPlan = pps[(p2p.rank - 1) as usize].clone();
-                    for v in ignored_p.plan.iter().map(|x| db!(x).idx).collect::<Vec<u32>>() {
-                        unsafe {
-                            IGNORED[v as usize] = false;
-                        }
+                    let packet_size = get_u32(&msg, 0) - 1;
+                    let packet = &msg[5..(5 + packet_size as usize)];
+                    match DELTA.lock().unwrap().push(packet) {
+                        Ok(Some(delta)) => on_delta(delta),
+                        Ok(None) => {}
+                        Err(e) => println!("Dropping bad delta packet from {}: {}", status.source_rank(), e),
                     }
-
-                    for rank in 0..pps.len() {
-                        for p in pps[rank].plan.iter() {
-                            unsafe { __partitions[db!(p).idx as usize] = rank as u32; }
-                        }
+                } else if pkt_type == 3 {
+                    let packet_size = get_u32(&msg, 0) - 1;
+                    let packet = &msg[5..(5 + packet_size as usize)];
+                    match ASSIGNMENT.lock().unwrap().push(packet) {
+                        Ok(Some(assignment)) => apply_assignment(&assignment, p2p.rank as u32),
+                        Ok(None) => {}
+                        Err(e) => println!("Dropping bad assignment packet from {}: {}", status.source_rank(), e),
                     }
-
                 } else if pkt_type == 2 {
                     let testcase_size = get_u32(&msg, 0) - 1;
                     let testcase = &msg[5..(5 + testcase_size as usize)];
@@ -142,6 +398,17 @@ fn sync_corpus(p2p: &P2P, dgraph: Rc<RefCell<DGraph>>) -> Vec<Vec<u8>> {
     }
 }
 
+// what the coordinator partitions: the execution model in use, by rarity
+// with PARTITION_WEIGHTING=rarity
+fn partitioned<'a>(dgraph: &'a DGraph, tree: &'a ETree, rarity: &'a mut Option<RarityView>) -> &'a dyn GraphView {
+    let model: &dyn GraphView = if *PATH_TREE { tree } else { dgraph };
+    if *RARITY_WEIGHTING {
+        rarity.insert(RarityView::new(model))
+    } else {
+        model
+    }
+}
+
 fn main() {
     let universe = mpi::initialize().unwrap();
     let world = universe.world();
@@ -150,42 +417,256 @@ fn main() {
     println!("Hello from process {} of {}", rank, world.size());
 
     let mut dgraph = DGraph::new();
+    // per rank, generation the next delta sent to it starts from
+    let mut acked = vec![0u64; world.size() as usize];
+    // per rank, packets of its execution tree delta received so far
+    let mut deltas: Vec<DeltaAssembler> = (0..world.size()).map(|_| DeltaAssembler::new()).collect();
+    // the coordinator owns the assignment and hands plans to workers in this order
+    let mut workers: Vec<u32> = (1..world.size() as u32).collect();
+    let hosts = host_names(&p2p);
+    let host_ranks = |workers: &[u32]| -> Vec<(u32, String)> {
+        workers.iter().map(|w| (*w, hosts[*w as usize].clone())).collect()
+    };
+    // functions or files, when partitioning by them
+    let mut code_units: Option<(CodeMap, Granularity)> = None;
+    let strategy: Box<dyn PartitionStrategy> = match PARTITION_STRATEGY.as_str() {
+        "hierarchical" => {
+            // split across hosts first, then across the ranks of each host
+            let (hierarchical, order) = Hierarchical::from_hosts(&host_ranks(&workers));
+            println!("Partitioning across {} hosts of {:?} ranks", hierarchical.hosts.len(), hierarchical.hosts);
+            workers = order;
+            Box::new(hierarchical)
+        }
+        "function" | "file" => {
+            let granularity = if PARTITION_STRATEGY.as_str() == "function" { Granularity::Function } else { Granularity::File };
+            // only the coordinator partitions, so only it symbolizes
+            let map = if rank == 0 { code_map() } else { CodeMap::default() };
+            code_units = Some((map.clone(), granularity));
+            Box::new(CodeUnits { map, granularity })
+        }
+        name => strategy_from_name(name).expect("unknown PARTITION_STRATEGY"),
+    };
+    let mut plans: Vec<PartitionPlan> = vec![];
+    let mut assignment = PartitionAssignment::default();
+    if rank == 0 {
+        if let Some(path) = RESUME_SNAPSHOT.as_ref() {
+            assert!(!*PATH_TREE, "snapshots hold a DGraph, they cannot resume EXECUTION_GRAPH=etree");
+            let snapshot = snapshot::load(std::path::Path::new(path)).expect("cannot load snapshot");
+            println!("Resuming from {}, assignment epoch {}", path, snapshot.assignment.epoch);
+            dgraph = snapshot.graph;
+            plans = snapshot.plans;
+            assignment = snapshot.assignment;
+        }
+    }
+    dgraph.set_replica(rank as u32);
+    dgraph.set_node_budget(*NODE_BUDGET);
+    dgraph.set_decay(*WEIGHT_DECAY);
+    // ranks heard from since the last epoch boundary
+    let mut reported = vec![false; world.size() as usize];
+    // the graph as the current epoch began, to report what the epoch discovered
+    let mut epoch_start = dgraph.clone();
+    // workers that synced within WORKER_TIMEOUT; plan i belongs to live[i]
+    let mut live = workers.clone();
+    let mut last_seen: HashMap<u32, Instant> = workers.iter().map(|w| (*w, Instant::now())).collect();
 
 
     if rank > 0 {
         let ignored = vec![false; 4096];
-        fuzz_process_epoch(
-            &p2p,
-            Rc::new(RefCell::new(dgraph)),
-            on_testcase_found,
-            on_execution_finished,
-            sync_corpus,
-            ignored,
-        );
+        if *PATH_TREE {
+            fuzz_process_epoch(
+                &p2p,
+                Rc::new(RefCell::new(ETree::default())),
+                on_testcase_found,
+                on_execution_finished_tree,
+                sync_corpus_tree,
+                ignored,
+            );
+        } else {
+            fuzz_process_epoch(
+                &p2p,
+                Rc::new(RefCell::new(dgraph)),
+                on_testcase_found,
+                on_execution_finished,
+                sync_corpus,
+                ignored,
+            );
+        }
     } else {
+        // with EXECUTION_GRAPH=etree, the paths all workers took
+        let mut tree = ETree::default();
+        // per rank, packets of its paths received so far
+        let mut paths: Vec<PacketAssembler> = (0..world.size()).map(|_| PacketAssembler::new()).collect();
+        // workers resume with the ownership of the snapshot
+        if !assignment.owners.is_empty() {
+            for packet in assignment.to_packets(4096 - 5) {
+                for worker in &workers {
+                    p2p.send(make_packet(3, &packet), *worker);
+                }
+            }
+        }
         loop {
             let (msg, status) = p2p.recv_any();
             let pkt_type = msg[4];
-            assert!(pkt_type == 0);
-
-            if pkt_type == 0 {
-                let from = status.source_rank();
-                let msg_size = get_u32(&msg, 0) as usize - 1;
-                dgraph.merge(
-                    &DGraph::deserialize(msg[5..(5 + msg_size)].to_vec())
-                );
-
-                let mut msg = vec![0; 5];
-                msg[4] = 1;
-                let mut serialized = dgraph.serialize();
-                let size = serialized.len() + 1;
-                msg[0] = (size >> 24) as u8;
-                msg[1] = (size >> 16) as u8;
-                msg[2] = (size >> 8) as u8;
-                msg[3] = size as u8;
-                msg.append(&mut serialized);
-
-                p2p.send(msg, from as u32);
+            let from = status.source_rank();
+            if pkt_type != 0 && pkt_type != 4 {
+                println!("Dropping packet of unexpected type {} from {}", pkt_type, from);
+                continue;
+            }
+
+            let msg_size = get_u32(&msg, 0) as usize - 1;
+            let packet = &msg[5..(5 + msg_size)];
+            if pkt_type == 4 {
+                let bytes = match paths[from as usize].push(packet) {
+                    Ok(Some(bytes)) => bytes,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        println!("Dropping bad paths packet from {}: {}", from, e);
+                        continue;
+                    }
+                };
+                match ETree::try_deserialize(bytes) {
+                    Ok(paths) => tree.merge(&paths),
+                    Err(e) => println!("Dropping bad paths from {}: {}", from, e),
+                }
+            } else {
+                let delta = match deltas[from as usize].push(packet) {
+                    Ok(Some(delta)) => delta,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        println!("Dropping bad delta packet from {}: {}", from, e);
+                        continue;
+                    }
+                };
+                dgraph.apply_delta(&delta, from as u32);
+
+                // history is only kept for live workers, one coming back
+                // (or starting) catches up with the whole graph
+                if !live.contains(&(from as u32)) {
+                    acked[from as usize] = 0;
+                }
+                let reply = if acked[from as usize] == 0 {
+                    dgraph.full_delta_for(from as u32)
+                } else {
+                    dgraph.delta_for(acked[from as usize], from as u32)
+                };
+                acked[from as usize] = reply.to;
+                dgraph.compact_history(live.iter().map(|w| acked[*w as usize]).min().unwrap_or(reply.to));
+
+                for packet in reply.to_packets(4096 - 5) {
+                    p2p.send(make_packet(1, &packet), from as u32);
+                }
+            }
+
+            // workers that went silent lose their partition, returning ones get one back
+            last_seen.insert(from as u32, Instant::now());
+            let next_live: Vec<u32> = workers.iter().copied()
+                .filter(|w| last_seen[w].elapsed() < *WORKER_TIMEOUT)
+                .collect();
+            // ranks that (re)joined may have missed the current assignment
+            let joined: Vec<u32> = next_live.iter().copied().filter(|w| !live.contains(w)).collect();
+            if next_live != live {
+                println!("Live workers changed from {:?} to {:?}", live, next_live);
+                if !plans.is_empty() && !next_live.is_empty() {
+                    let mut rarity = None;
+                    let graph = partitioned(&dgraph, &tree, &mut rarity);
+                    let units = strategy.units(graph, next_live.len());
+                    let reassignment = partition::reassign_workers(graph, &plans, &live, &next_live,
+                                                                   REPARTITION_IMBALANCE_PCT, &units);
+                    println!("Reassigned for live workers, {} nodes migrated", reassignment.migrated);
+                    plans = reassignment.plans;
+                } else {
+                    plans = vec![];
+                }
+                live = next_live;
+            }
+
+            // an epoch ends once every live worker synced at least once
+            reported[from as usize] = true;
+            let epoch_ended = live.iter().all(|worker| reported[*worker as usize]);
+            if epoch_ended {
+                reported.iter_mut().for_each(|r| *r = false);
+                // decay and snapshots only exist for the edge graph
+                if !*PATH_TREE {
+                    println!("Epoch {}: discovered {}", assignment.epoch, epoch_start.diff(&dgraph));
+                    dgraph.end_epoch();
+                    epoch_start = dgraph.clone();
+                    if let Some(path) = SNAPSHOT_PATH.as_ref() {
+                        if let Err(e) = snapshot::save(std::path::Path::new(path), &dgraph, &assignment, &plans) {
+                            println!("Cannot save snapshot to {}: {}", path, e);
+                        }
+                    }
+                }
+            }
+
+            // one deterministic assignment for everyone, re-sent only when it
+            // changes or to ranks that just joined
+            if live.is_empty() {
+                continue;
+            }
+            // hierarchical plans follow the hosts of the workers still live,
+            // which keep the host order of `workers`
+            let live_hosts;
+            let strategy: &dyn PartitionStrategy = if PARTITION_STRATEGY.as_str() == "hierarchical" {
+                live_hosts = Hierarchical::from_hosts(&host_ranks(&live)).0;
+                &live_hosts
+            } else {
+                strategy.as_ref()
+            };
+            let mut rarity = None;
+            let graph = partitioned(&dgraph, &tree, &mut rarity);
+            // the strategy decides once per epoch, in between new nodes only
+            // join the plans around them
+            plans = if plans.is_empty() {
+                strategy.partition(graph, live.len())
+            } else if epoch_ended {
+                let reassignment = partition::stable_partition(strategy, graph, &plans, live.len());
+                println!("Re-ran {}, {} nodes migrated", PARTITION_STRATEGY.as_str(), reassignment.migrated);
+                reassignment.plans
+            } else {
+                let units = strategy.units(graph, live.len());
+                let reassignment = partition::reassign_units(graph, &plans, live.len(), REPARTITION_IMBALANCE_PCT, &units);
+                println!("Repartitioned, {} nodes migrated", reassignment.migrated);
+                reassignment.plans
+            };
+            if epoch_ended {
+                println!("Epoch {}: graph {}", assignment.epoch, graph_stats(graph));
+                println!("Epoch {}: partitions {}", assignment.epoch, partition_quality(graph, &plans));
+            }
+            let next = PartitionAssignment::from_plans(assignment.epoch + 1, &plans, &live);
+            if next.same_owners(&assignment) {
+                for packet in assignment.to_packets(4096 - 5) {
+                    for worker in &joined {
+                        p2p.send(make_packet(3, &packet), *worker);
+                    }
+                }
+                continue;
+            }
+            assignment = next;
+            for bottleneck in DominatorTree::new(graph).bottlenecks(graph, TOP_BOTTLENECKS) {
+                match code_units.as_ref().and_then(|(map, _)| map.function(edge_of(bottleneck.key.0))) {
+                    Some(symbol) => println!("Epoch {}: bottleneck {} in {}", assignment.epoch, bottleneck, symbol.function),
+                    None => println!("Epoch {}: bottleneck {}", assignment.epoch, bottleneck),
+                }
+            }
+            if let Some((map, granularity)) = code_units.as_ref() {
+                for (plan, worker) in plans.iter().zip(&live) {
+                    let names = map.unit_names(&plan.plan, *granularity);
+                    println!("Epoch {}: rank {} owns {}", assignment.epoch, worker, names.join(", "));
+                }
+            }
+            if let Some(dir) = GRAPH_DUMP_DIR.as_ref() {
+                let path = format!("{}/epoch-{}", dir, assignment.epoch);
+                for (file, contents) in [(format!("{}.dot", path), export::to_dot(graph, &plans)),
+                                         (format!("{}.json", path), export::to_json(graph, &plans))] {
+                    if let Err(e) = std::fs::write(&file, contents) {
+                        println!("Cannot dump graph to {}: {}", file, e);
+                    }
+                }
+            }
+            for packet in assignment.to_packets(4096 - 5) {
+                for worker in &live {
+                    p2p.send(make_packet(3, &packet), *worker);
+                }
             }
         }
     }